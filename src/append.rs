@@ -0,0 +1,204 @@
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+use crate::deflate::Deflate;
+use crate::join::{open_reader, read_member, BARE_HEADER};
+use crate::trees::Trees;
+use crate::{crc_update, GzipState, Trailing, DEFLATED, ERROR, WSIZE};
+
+/// Output of the last member of the file being appended to, as it is
+/// decoded. Only what the current block may still refer to is kept: the
+/// data from WSIZE bytes before its start on. The rest is folded into the
+/// CRC-32 register.
+struct Tail {
+    base: u64, // output offset of data[0]
+    data: Vec<u8>,
+    crc: u32, // CRC-32 register of the output before base
+}
+
+impl Tail {
+    // A block starts at output offset mark
+    fn mark(&mut self, mark: u64) {
+        let keep = mark.saturating_sub(WSIZE as u64);
+        if keep > self.base {
+            let n = ((keep - self.base) as usize).min(self.data.len());
+            self.crc = crc_update(self.crc, &self.data[..n]);
+            self.data.drain(..n);
+            self.base += n as u64;
+        }
+    }
+}
+
+struct TailSink(Rc<RefCell<Tail>>);
+
+impl Write for TailSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where compression resumes in the file being appended to.
+struct Resume {
+    start: u64,    // bit offset of the header of the last block
+    crc: u32,      // CRC-32 register of the member output before that block
+    len: u64,      // length of that output
+    dict: Vec<u8>, // its last WSIZE bytes
+    tail: Vec<u8>, // output of the last block, compressed again
+}
+
+/// Append the files in names (stdin if there are none) to the gzip file
+/// target without recompressing it, as gzappend does: the last member is
+/// decoded to find its last block, which is compressed again together with
+/// the new data, with the window primed from the output before it. The
+/// trailer is then rewritten, so the file stays a single member. A missing
+/// or empty target is created.
+pub fn append(state: &mut GzipState, target: &Path, names: &[OsString]) -> io::Result<()> {
+    // Open all the new data first: a missing file leaves target unchanged
+    let mut input: Box<dyn Read> = Box::new(io::empty());
+    for name in names {
+        if name == "-" {
+            input = Box::new(input.chain(io::stdin()));
+            continue;
+        }
+        match File::open(name) {
+            Ok(file) => input = Box::new(input.chain(file)),
+            Err(err) => {
+                eprintln!("{}: {}: {}", state.program_name, Path::new(name).display(), err);
+                state.exit_code = ERROR;
+                return Ok(());
+            }
+        }
+    }
+    if names.is_empty() {
+        input = Box::new(io::stdin());
+    }
+
+    let existing = match fs::metadata(target) {
+        Ok(meta) => meta.len() > 0,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => {
+            eprintln!("{}: {}: {}", state.program_name, target.display(), err);
+            state.exit_code = ERROR;
+            return Ok(());
+        }
+    };
+    let resume = if existing {
+        match find_last_block(state, target) {
+            Ok(Some(resume)) => resume,
+            Ok(None) => {
+                state.exit_code = ERROR;
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("{}: {}: {}", state.program_name, target.display(), err);
+                state.exit_code = ERROR;
+                return Ok(());
+            }
+        }
+    } else {
+        let mut file = File::create(target)?;
+        file.write_all(&BARE_HEADER)?;
+        Resume { start: BARE_HEADER.len() as u64 * 8, crc: 0xffffffff, len: 0, dict: Vec::new(), tail: Vec::new() }
+    };
+
+    // The bits of the last byte kept that come before the last block go
+    // back into the bit buffer, to be written again with what follows
+    let mut file = OpenOptions::new().read(true).write(true).open(target)?;
+    let offset = resume.start / 8;
+    let used_bits = (resume.start % 8) as u8;
+    let mut byte = [0u8];
+    if used_bits != 0 {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut byte)?;
+    }
+    file.seek(SeekFrom::Start(offset))?;
+
+    state.ifd = Some(Box::new(io::Cursor::new(resume.tail).chain(input)));
+    state.ofd = Some(Box::new(file.try_clone()?));
+    state.clear_bufs();
+    state.bytes_in = 0;
+    state.bytes_out = 0;
+    state.ifile_size = -1;
+    state.method = DEFLATED;
+    state.bi_buf = u16::from(byte[0] & ((1u16 << used_bits) - 1) as u8);
+    state.bi_valid = used_bits;
+    state.crc16_digest = crc_update(resume.crc, &resume.dict);
+
+    let mut trees = Trees::with_mem_level(state.mem_level);
+    let mut deflate = Deflate::with_params(state.window_bits, state.mem_level);
+    let mut attr = 0;
+    let mut deflate_flags = 0;
+    trees.ct_init(&mut attr, state.method);
+    trees.strategy = state.strategy;
+    deflate.set_strategy(state.strategy);
+    deflate.set_rsyncable(state.rsyncable);
+    deflate.set_dictionary(&resume.dict);
+    deflate.lm_init(state, state.level, &mut deflate_flags);
+    deflate.deflate(&mut trees, state)?;
+
+    let crc = state.updcrc(Some(&[]), 0);
+    let len = resume.len + state.bytes_in as u64;
+    state.put_long(crc)?;
+    state.put_long(len as u32)?;
+    state.flush_outbuf()?;
+    file.set_len(offset + state.bytes_out as u64)?;
+    if state.synchronous {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+// Decode the members of target to find the last block of the last one. None
+// once the problem has been reported.
+fn find_last_block(state: &mut GzipState, target: &Path) -> io::Result<Option<Resume>> {
+    let tail = Rc::new(RefCell::new(Tail { base: 0, data: Vec::new(), crc: 0xffffffff }));
+    let (mut reader, mut input) = open_reader(state, target, Box::new(TailSink(tail.clone())))?;
+    // Data after the last member would end up before the new data
+    reader.trailing = Trailing::Ignore;
+    let mut found = None;
+    loop {
+        let method = reader.get_method(&mut input)?;
+        if method.is_none() && reader.method != -1 && found.is_some() {
+            reader.file_error("trailing data after the last member -- unchanged");
+            return Ok(None);
+        }
+        if method != Some(DEFLATED) || reader.last_member {
+            if method.is_some() {
+                reader.file_error("not a gzip file");
+            }
+            return Ok(None);
+        }
+
+        *tail.borrow_mut() = Tail { base: 0, data: Vec::new(), crc: 0xffffffff };
+        let mut mark = 0;
+        let on_block = |pos| {
+            mark = pos;
+            tail.borrow_mut().mark(pos);
+        };
+        match read_member(&mut reader, on_block)? {
+            Some(member) => found = Some((member.last, mark)),
+            None => return Ok(None),
+        }
+
+        if reader.input_eof()? {
+            break;
+        }
+    }
+    if reader.exit_code == ERROR {
+        return Ok(None);
+    }
+
+    let (start, mark) = found.unwrap();
+    let mut kept = tail.borrow_mut();
+    let at = (mark - kept.base) as usize;
+    let rest = kept.data.split_off(at);
+    Ok(Some(Resume { start, crc: kept.crc, len: mark, dict: std::mem::take(&mut kept.data), tail: rest }))
+}
@@ -1,22 +1,25 @@
 use std::io;
-use crate::GzipState;
-use crate::trees::Trees;
+use crate::{adler32, Container, GzipState};
+use crate::trees::{lit_bufsize, Trees};
 
 const NIL: u16 = 0;
-const HASH_SIZE: usize = 1 << 15; // Assuming HASH_BITS == 15
-const WSIZE: usize = 32 * 1024; // Window size (32K)
-const WMASK: usize = WSIZE - 1;
+const WSIZE: usize = 32 * 1024; // Largest window size (32K)
+pub(crate) const MIN_WBITS: u32 = 9; // window of 512 bytes
+pub(crate) const MAX_WBITS: u32 = 15; // window of 32K, the deflate limit
+pub(crate) const DEF_MEM_LEVEL: u32 = 8; // 32K hash heads and literals
+pub(crate) const MAX_MEM_LEVEL: u32 = 9;
 const MIN_LOOKAHEAD: usize = 262; // Minimum lookahead for deflate
 pub(crate) const MIN_MATCH: usize = 3;
 const FAST: u16 = 0x04;
 const SLOW: u16 = 0x02;
-pub(crate) const MAX_DIST: usize = WSIZE-MIN_LOOKAHEAD;
+pub(crate) const MAX_DIST: usize = WSIZE-MIN_LOOKAHEAD; // for the largest window
 // pub(crate) const MAX_DIST: usize = 16384;
 pub(crate) const MAX_MATCH: usize = 258;
-const HASH_BITS: usize = 15;
-const HASH_MASK: u32 = (HASH_SIZE as u32) - 1;
-const WINDOW_SIZE: usize = 2*WSIZE;
-const H_SHIFT: u32 = ((HASH_BITS + MIN_MATCH - 1) / MIN_MATCH) as u32; // 5
+pub(crate) const ULTRA_LEVEL: i32 = 11; // optimal parsing, selected with -11 or --ultra
+const ULTRA_BLOCK: usize = 16384; // input bytes parsed per block at the ultra level
+const ULTRA_MAX_ITER: usize = 15; // cost model refinements per block
+const FILTERED_MIN_MATCH: usize = 6; // shortest match kept by the filtered strategy
+const RSYNC_WIN: usize = 4096; // bytes covered by the --rsyncable rolling sum
 const CONFIGURATION_TABLE: [Config; 10] = [
     /* 0 */ Config::new(0, 0, 0, 0), /* store only */
     /* 1 */ Config::new(4, 4, 8, 4), /* maximum speed, no lazy matches */
@@ -29,6 +32,38 @@ const CONFIGURATION_TABLE: [Config; 10] = [
     /* 8 */ Config::new(32, 128, 258, 1024),
     /* 9 */ Config::new(32, 258, 258, 4096)];
 
+/// Compression strategies, equivalent to zlib's Z_DEFAULT_STRATEGY,
+/// Z_FILTERED, Z_HUFFMAN_ONLY, Z_RLE and Z_FIXED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Normal string matching.
+    #[default]
+    Default,
+    /// Data produced by a filter or predictor: short matches are dropped so
+    /// that more of the data is left to the Huffman coder.
+    Filtered,
+    /// No string matching at all, literals only.
+    HuffmanOnly,
+    /// Matches restricted to distance 1 (run-length encoding).
+    Rle,
+    /// Always use the static Huffman trees.
+    Fixed,
+}
+
+impl Strategy {
+    /// Parse a strategy name as given to --strategy.
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        match name {
+            "default" => Some(Strategy::Default),
+            "filtered" => Some(Strategy::Filtered),
+            "huffman" | "huffman-only" => Some(Strategy::HuffmanOnly),
+            "rle" => Some(Strategy::Rle),
+            "fixed" => Some(Strategy::Fixed),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Default)]
 struct Config {
     max_lazy: i32,
@@ -52,14 +87,21 @@ impl Config {
 }
 pub struct Deflate {
     compr_level: i32,
-    head: [u16; HASH_SIZE],
+    w_size: usize,              // Window size, a power of two
+    w_mask: usize,
+    max_dist: usize,            // Longest distance a match may reach back
+    window_size: usize,         // Bytes in window, twice w_size
+    hash_mask: u32,
+    hash_shift: u32,            // Bits to shift per byte, so that a hash covers MIN_MATCH bytes
+    ultra_block: usize,         // Bytes parsed per block at the ultra level
+    head: Vec<u16>,
     max_lazy_match: i32,
     good_match: i32,
     nice_match: i32,
     max_chain_length: i32,
     pub(crate) strstart: usize,
     pub(crate) block_start: i64,
-    window: [u8; 2 * WSIZE],
+    window: Vec<u8>,
     eofile: bool,
     lookahead: usize,
     ins_h: u32,
@@ -67,35 +109,107 @@ pub struct Deflate {
     prev_length: usize,
     match_start: usize,
     max_insert_length: usize,
+    strategy: Strategy,
+    dict_len: usize,            // Preset dictionary bytes at the start of the window
+    rsyncable: bool,
+    rsync_win: usize,           // Bytes in the rolling sum, at most max_dist
+    rsync_sum: u64,             // Sum of the last rsync_win input bytes
+    rsync_filled: usize,        // Bytes summed so far, until rsync_win
+    rsync_chunk_end: Option<usize>, // Window position ending the current chunk
 }
 
 impl Deflate {
     pub fn new() -> Self {
+        Self::with_params(MAX_WBITS, DEF_MEM_LEVEL)
+    }
+
+    /// Create a compressor with a window of 2^window_bits bytes (9..=15)
+    /// and a memory level (1..=9) that sizes the hash table; Trees must be
+    /// created with the same memory level.
+    pub fn with_params(window_bits: u32, mem_level: u32) -> Self {
+        assert!((MIN_WBITS..=MAX_WBITS).contains(&window_bits), "bad window bits");
+        assert!((1..=MAX_MEM_LEVEL).contains(&mem_level), "bad memory level");
+        let w_size = 1usize << window_bits;
+        let hash_bits = mem_level + 7;
         Self {
             compr_level: 0,
-            head: [NIL; HASH_SIZE],
+            w_size,
+            w_mask: w_size - 1,
+            max_dist: w_size - MIN_LOOKAHEAD,
+            window_size: 2 * w_size,
+            hash_mask: (1 << hash_bits) - 1,
+            hash_shift: hash_bits.div_ceil(MIN_MATCH as u32),
+            // A block and its lookahead must fit in the window, and its
+            // symbols in the literal buffer
+            ultra_block: ULTRA_BLOCK
+                .min(w_size - MIN_LOOKAHEAD)
+                .min(lit_bufsize(mem_level) - 1),
+            head: vec![NIL; 1 << hash_bits],
             max_lazy_match: 0,
             good_match: 0,
             nice_match: 0,
             max_chain_length: 0,
             strstart: 0,
             block_start: 0,
-            window: [0; 2 * WSIZE],
+            window: vec![0; 2 * w_size],
             eofile: false,
             lookahead: 0,
             ins_h: 0,
-            prev: vec![0; WSIZE],
+            prev: vec![0; w_size],
             prev_length: 0,
             match_start: 0,
-            max_insert_length: 0
+            max_insert_length: 0,
+            strategy: Strategy::Default,
+            dict_len: 0,
+            rsyncable: false,
+            rsync_win: RSYNC_WIN.min(w_size - MIN_LOOKAHEAD),
+            rsync_sum: 0,
+            rsync_filled: 0,
+            rsync_chunk_end: None,
+        }
+    }
+
+    /// Select the compression strategy. Must be called before deflate().
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    /// Cut blocks at content-defined boundaries so that the output
+    /// resynchronises after a local change of the input (--rsyncable).
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.rsyncable = rsyncable;
+    }
+
+    /// Window size in bits, as stored in the zlib CINFO field.
+    pub fn window_bits(&self) -> u32 {
+        self.w_size.trailing_zeros()
+    }
+
+    /// Prime the window with a preset dictionary. Only the last max_dist
+    /// bytes can be referenced by the input. Must be called before lm_init(),
+    /// which inserts the dictionary strings into the hash chains.
+    pub fn set_dictionary(&mut self, dict: &[u8]) {
+        let dict = &dict[dict.len().saturating_sub(self.max_dist)..];
+        self.window[..dict.len()].copy_from_slice(dict);
+        self.dict_len = dict.len();
+    }
+
+    /// Shortest match the current strategy accepts.
+    fn shortest_match(&self) -> usize {
+        if self.strategy == Strategy::Filtered {
+            FILTERED_MIN_MATCH
+        } else {
+            MIN_MATCH
         }
     }
 
     pub fn lm_init(&mut self, state: &mut GzipState, pack_level: i32, flags: &mut u16) {
-        if pack_level < 1 || pack_level > 9 {
+        if (pack_level < 1 || pack_level > 9) && pack_level != ULTRA_LEVEL {
             state.gzip_error("bad pack level");
         }
         self.compr_level = pack_level;
+        // The ultra level searches the hash chains like level 9
+        let pack_level = pack_level.min(9);
 
         // Initialize the hash table.
         self.head.fill(NIL);
@@ -113,14 +227,21 @@ impl Deflate {
 
         if pack_level == 1 {
             *flags |= FAST;
-        } else if pack_level == 9 {
+        } else if pack_level == 9 && self.w_size == WSIZE {
+            // A reduced window does not give maximum compression
             *flags |= SLOW;
         }
 
-        self.strstart = 0;
-        self.block_start = 0;
+        self.rsync_sum = 0;
+        self.rsync_filled = 0;
+        self.rsync_chunk_end = None;
 
-        (self.lookahead, self.eofile) = Self::read_buf(state, &mut self.window, 2 * WSIZE);
+        // The input starts right after the preset dictionary, if any
+        self.strstart = self.dict_len;
+        self.block_start = self.dict_len as i64;
+
+        (self.lookahead, self.eofile) =
+            Self::read_buf(state, &mut self.window[self.dict_len..], self.window_size - self.dict_len);
 
         if self.lookahead == 0 {
             self.eofile = true;
@@ -137,6 +258,9 @@ impl Deflate {
         for j in 0..(MIN_MATCH - 1) {
             self.ins_h = self.update_hash(self.ins_h, self.window[j]);
         }
+        for s in 0..self.dict_len {
+            self.insert_string(s);
+        }
     }
 
     fn update_hash(&self, h: u32, c: u8) -> u32 {
@@ -144,7 +268,7 @@ impl Deflate {
         let c_u32 = u32::from(c);
         // println!("h: {}", h);
         // println!("c: {}", c);
-        ((h << H_SHIFT) ^ c_u32) & HASH_MASK
+        ((h << self.hash_shift) ^ c_u32) & self.hash_mask
     }
 
     fn read_buf(state: &mut GzipState, buf: &mut [u8], size: usize) -> (usize, bool) {
@@ -152,17 +276,16 @@ impl Deflate {
             match input.read(&mut buf[..size]) {
                 Ok(bytes_read) => {
                     if bytes_read > 0 {
-                        if state.first_call {
-                            // Initialize CRC
-                            state.updcrc(None, 0);
-                            state.first_call = false; // Set to false after the first call
-                        }
                         // 计算 CRC
                         state.crc = state.updcrc(Some(&buf[..bytes_read]), bytes_read);
+                        if state.container == Container::Zlib {
+                            state.adler = adler32(state.adler, &buf[..bytes_read]);
+                        }
                     }else{
                         buf.fill(0);
                     }
                     state.bytes_in += bytes_read as i64;
+                    state.report_progress(false);
                     (bytes_read, bytes_read == 0)
                 }
                 Err(e) => {
@@ -177,35 +300,12 @@ impl Deflate {
 
     fn fill_window(&mut self, state: &mut GzipState) {
         // Move the existing data if necessary
-        if self.strstart >= WSIZE + MAX_DIST {
-            // Shift the window
-            self.window.copy_within(WSIZE..2 * WSIZE, 0);
-            self.strstart -= WSIZE;
-            self.block_start -= WSIZE as i64;
-
-            // Adjust the hash table
-            for i in 0..HASH_SIZE {
-                let h = self.head[i];
-                if h as usize >= WSIZE {
-                    self.head[i] = h - WSIZE as u16;
-                } else {
-                    self.head[i] = NIL;
-                }
-            }
-
-            // Adjust the `prev` table
-            for i in 0..WSIZE {
-                let p = self.prev[i];
-                if p as usize >= WSIZE {
-                    self.prev[i] = p - WSIZE as u16;
-                } else {
-                    self.prev[i] = NIL;
-                }
-            }
+        if self.strstart >= self.w_size + self.max_dist {
+            self.slide_window();
         }
 
         // Read new data into the window
-        let available_space = (2 * WSIZE) - self.lookahead - self.strstart;
+        let available_space = self.window_size - self.lookahead - self.strstart;
         let (n, eof) = Self::read_buf(
             state,
             &mut self.window[self.strstart + self.lookahead..],
@@ -218,7 +318,38 @@ impl Deflate {
         }
     }
 
+    /// Move the upper half of the window to the lower half and adjust the
+    /// hash chains accordingly.
+    fn slide_window(&mut self) {
+        // Shift the window
+        let w_size = self.w_size;
+        self.window.copy_within(w_size..2 * w_size, 0);
+        self.strstart -= w_size;
+        self.block_start -= w_size as i64;
+        if let Some(end) = self.rsync_chunk_end.as_mut() {
+            *end -= w_size;
+        }
+
+        // Adjust the hash table
+        for h in self.head.iter_mut() {
+            *h = if *h as usize >= w_size { *h - w_size as u16 } else { NIL };
+        }
+
+        // Adjust the `prev` table
+        for p in self.prev.iter_mut() {
+            *p = if *p as usize >= w_size { *p - w_size as u16 } else { NIL };
+        }
+    }
+
     pub fn deflate(&mut self, trees: &mut Trees, state: &mut GzipState) -> io::Result<()> {
+        match self.strategy {
+            Strategy::HuffmanOnly => return self.deflate_huff(trees, state),
+            Strategy::Rle => return self.deflate_rle(trees, state),
+            _ => {}
+        }
+        if self.compr_level == ULTRA_LEVEL {
+            return self.deflate_ultra(trees, state);
+        }
         if self.compr_level <= 3 {
             return self.deflate_fast(trees, state);
         }
@@ -242,10 +373,10 @@ impl Deflate {
 
             // Find the longest match, discarding those <= prev_length
             // At this point, we always have match_length < MIN_MATCH
-            if hash_head != NIL.into()
+            if hash_head != usize::from(NIL)
                 && self.strstart > hash_head
-                && self.strstart - hash_head <= MAX_DIST
-                && self.strstart <= WINDOW_SIZE - MIN_LOOKAHEAD
+                && self.strstart - hash_head <= self.max_dist
+                && self.strstart <= self.window_size - MIN_LOOKAHEAD
             {
                 // To prevent matches with the string of window index 0
                 match_length = self.longest_match(hash_head);
@@ -257,6 +388,9 @@ impl Deflate {
             // println!("strstart: {}", self.strstart);
             // println!("hash_head: {}", hash_head);
             // println!("dbg: match_length: {}", match_length);
+            if match_length < self.shortest_match() {
+                match_length = MIN_MATCH - 1;
+            }
             if match_length >= MIN_MATCH {
                 self.check_match(state, self.strstart, self.match_start, match_length);
 
@@ -265,6 +399,7 @@ impl Deflate {
                 flush = tree.ct_tally(self, state, self.strstart - self.match_start, match_length - MIN_MATCH);
 
                 self.lookahead -= match_length;
+                self.rsync_roll(self.strstart, match_length);
 
                 // Insert new strings in the hash table only if the match length is not too large
                 if match_length <= self.max_lazy_match as usize {
@@ -293,12 +428,14 @@ impl Deflate {
                 // No match, output a literal byte
                 // println!("no match");
                 flush = tree.ct_tally(self, state, 0, self.window[self.strstart] as usize);
+                self.rsync_roll(self.strstart, 1);
                 self.lookahead -= 1;
                 self.strstart += 1;
             }
             // println!("flush={:?}",flush);
-            if flush  {
-                self.flush_block_wrapper(tree, state, false);
+            let pad = self.rsync_boundary();
+            if flush || pad {
+                self.flush_block_wrapper(tree, state, pad, false);
                 self.block_start = self.strstart as i64;
             }
 
@@ -308,11 +445,276 @@ impl Deflate {
             }
             // println!("self.block_start: {:?}", self.block_start);
         }
-        self.flush_block_wrapper(tree, state, true);
+        self.flush_block_wrapper(tree, state, false, true);
+        Ok(())
+    }
+
+    /// Huffman-only strategy: every byte is sent as a literal.
+    pub fn deflate_huff(&mut self, tree: &mut Trees, state: &mut GzipState) -> io::Result<()> {
+        while self.lookahead != 0 {
+            let flush = tree.ct_tally(self, state, 0, self.window[self.strstart] as usize);
+            self.rsync_roll(self.strstart, 1);
+            self.lookahead -= 1;
+            self.strstart += 1;
+            let pad = self.rsync_boundary();
+            if flush || pad {
+                self.flush_block_wrapper(tree, state, pad, false);
+                self.block_start = self.strstart as i64;
+            }
+
+            while self.lookahead < MIN_LOOKAHEAD && !self.eofile {
+                self.fill_window(state);
+            }
+        }
+        self.flush_block_wrapper(tree, state, false, true);
+        Ok(())
+    }
+
+    /// RLE strategy: only runs of the previous byte (distance 1) are matched.
+    pub fn deflate_rle(&mut self, tree: &mut Trees, state: &mut GzipState) -> io::Result<()> {
+        while self.lookahead != 0 {
+            let mut match_length = 0;
+            if self.lookahead >= MIN_MATCH && self.strstart > 0 {
+                let prev = self.window[self.strstart - 1];
+                let max_len = self.lookahead.min(MAX_MATCH);
+                while match_length < max_len && self.window[self.strstart + match_length] == prev {
+                    match_length += 1;
+                }
+            }
+
+            let flush = if match_length >= MIN_MATCH {
+                self.check_match(state, self.strstart, self.strstart - 1, match_length);
+                let flush = tree.ct_tally(self, state, 1, match_length - MIN_MATCH);
+                self.rsync_roll(self.strstart, match_length);
+                self.lookahead -= match_length;
+                self.strstart += match_length;
+                flush
+            } else {
+                let flush = tree.ct_tally(self, state, 0, self.window[self.strstart] as usize);
+                self.rsync_roll(self.strstart, 1);
+                self.lookahead -= 1;
+                self.strstart += 1;
+                flush
+            };
+            let pad = self.rsync_boundary();
+            if flush || pad {
+                self.flush_block_wrapper(tree, state, pad, false);
+                self.block_start = self.strstart as i64;
+            }
+
+            while self.lookahead < MIN_LOOKAHEAD && !self.eofile {
+                self.fill_window(state);
+            }
+        }
+        self.flush_block_wrapper(tree, state, false, true);
+        Ok(())
+    }
+
+    /// Optimal parsing for the ultra level. The input is cut into blocks of
+    /// at most ULTRA_BLOCK bytes; for each block all matches are collected
+    /// from the hash chains, then the cheapest literal/match path is found by
+    /// dynamic programming. The path is tallied into the trees and the bit lengths
+    /// produced by build_tree become the cost model of the next pass, until
+    /// the cost of the block stops decreasing. The best path is emitted with
+    /// the usual flush_block, so the output remains standard deflate.
+    pub fn deflate_ultra(&mut self, trees: &mut Trees, state: &mut GzipState) -> io::Result<()> {
+        let mut matches: Vec<Vec<(u16, u16)>> = vec![Vec::new(); self.ultra_block];
+
+        loop {
+            // Keep the whole block and its lookahead inside the window
+            if self.strstart >= self.w_size
+                && self.strstart + self.ultra_block > self.window_size - MIN_LOOKAHEAD
+            {
+                self.slide_window();
+            }
+            while !self.eofile
+                && self.lookahead < self.ultra_block + MIN_LOOKAHEAD
+                && self.strstart + self.lookahead < self.window_size
+            {
+                self.fill_window(state);
+            }
+
+            let mut block_len = self.lookahead.min(self.ultra_block);
+            let mut pad = false;
+            if self.rsyncable {
+                // End the block at the first content-defined boundary
+                let boundary = (0..block_len).find(|&i| {
+                    self.rsync_roll(self.strstart + i, 1);
+                    self.rsync_chunk_end.take().is_some()
+                });
+                if let Some(i) = boundary {
+                    block_len = i + 1;
+                    pad = true;
+                }
+            }
+            let eof = self.eofile && block_len == self.lookahead;
+
+            // Insert every string of the block and record its matches
+            for (i, found) in matches[..block_len].iter_mut().enumerate() {
+                let hash_head = self.insert_string(self.strstart + i);
+                self.ultra_matches(self.strstart + i, hash_head, block_len - i, found);
+            }
+
+            let (mut llens, mut dlens) = trees.static_bit_lengths();
+            let mut best_path = Vec::new();
+            let mut best_cost = u64::MAX;
+            // With fixed trees the static cost model is already exact
+            let iterations = match (block_len, self.strategy) {
+                (0, _) => 0,
+                (_, Strategy::Fixed) => 1,
+                _ => ULTRA_MAX_ITER,
+            };
+            for _ in 0..iterations {
+                let path = self.ultra_path(trees, &llens, &dlens, &matches[..block_len]);
+                self.ultra_tally(trees, state, &path);
+                let (l, d, cost) = trees.dynamic_bit_lengths(state);
+                if cost >= best_cost {
+                    break;
+                }
+                best_cost = cost;
+                best_path = path;
+                llens = l;
+                dlens = d;
+            }
+
+            self.ultra_tally(trees, state, &best_path);
+            self.lookahead -= block_len;
+            self.flush_block_wrapper(trees, state, pad && !eof, eof);
+            self.block_start = self.strstart as i64;
+            if eof {
+                break;
+            }
+        }
         Ok(())
     }
 
-    fn flush_block_wrapper(&mut self, trees: &mut Trees, state: &mut GzipState, eof: bool) -> i64 {
+    /// Collect the matches for the string at `pos`, following the hash chain
+    /// from `cur_match`. Each (len, dist) entry means that all lengths above
+    /// the previous entry and up to `len` are available at distance `dist`,
+    /// which is the nearest one for those lengths.
+    fn ultra_matches(&self, pos: usize, mut cur_match: usize, max_len: usize, out: &mut Vec<(u16, u16)>) {
+        out.clear();
+        let max_len = max_len.min(MAX_MATCH);
+        if max_len < MIN_MATCH {
+            return;
+        }
+        let limit = pos.saturating_sub(self.max_dist);
+        let mut chain_length = self.max_chain_length;
+        let mut best_len = self.shortest_match() - 1;
+        let window = &self.window;
+
+        while cur_match > limit && cur_match < pos && chain_length > 0 {
+            chain_length -= 1;
+            if window[cur_match + best_len] == window[pos + best_len] {
+                let mut len = 0;
+                while len < max_len && window[pos + len] == window[cur_match + len] {
+                    len += 1;
+                }
+                if len > best_len {
+                    out.push((len as u16, (pos - cur_match) as u16));
+                    best_len = len;
+                    if len >= max_len {
+                        break;
+                    }
+                }
+            }
+            cur_match = self.prev[cur_match & self.w_mask] as usize;
+        }
+    }
+
+    /// Find the cheapest sequence of literals and matches covering the block
+    /// that starts at block_start, given the code lengths of the cost model.
+    /// Returns (length, distance) steps; a length of 1 is a literal.
+    fn ultra_path(&self, trees: &Trees, llens: &[u16], dlens: &[u16], matches: &[Vec<(u16, u16)>]) -> Vec<(u16, u16)> {
+        let block_len = matches.len();
+        let start = self.block_start as usize;
+        let mut length_cost = [0u32; MAX_MATCH + 1];
+        for (len, cost) in length_cost.iter_mut().enumerate().skip(MIN_MATCH) {
+            *cost = trees.length_cost(llens, len);
+        }
+
+        let mut cost = vec![u32::MAX; block_len + 1];
+        let mut step = vec![(0u16, 0u16); block_len + 1];
+        cost[0] = 0;
+        for i in 0..block_len {
+            let base = cost[i];
+            let c = base + trees.literal_cost(llens, self.window[start + i]);
+            if c < cost[i + 1] {
+                cost[i + 1] = c;
+                step[i + 1] = (1, 0);
+            }
+
+            let mut prev_len = self.shortest_match() - 1;
+            for &(len, dist) in &matches[i] {
+                let dist_cost = base + trees.dist_cost(dlens, dist as usize);
+                for l in (prev_len + 1)..=len as usize {
+                    let c = dist_cost + length_cost[l];
+                    if c < cost[i + l] {
+                        cost[i + l] = c;
+                        step[i + l] = (l as u16, dist);
+                    }
+                }
+                prev_len = len as usize;
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut i = block_len;
+        while i > 0 {
+            path.push(step[i]);
+            i -= step[i].0 as usize;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Tally a parsed path into the trees, advancing strstart over the block.
+    fn ultra_tally(&mut self, trees: &mut Trees, state: &mut GzipState, path: &[(u16, u16)]) {
+        self.strstart = self.block_start as usize;
+        for &(len, dist) in path {
+            if len == 1 {
+                trees.ct_tally(self, state, 0, self.window[self.strstart] as usize);
+            } else {
+                self.check_match(state, self.strstart, self.strstart - dist as usize, len as usize);
+                trees.ct_tally(self, state, dist as usize, len as usize - MIN_MATCH);
+            }
+            self.strstart += len as usize;
+        }
+    }
+
+    /// Update the --rsyncable rolling sum with the `num` bytes at `start`, and
+    /// record the first position where the sum hits the trigger.
+    fn rsync_roll(&mut self, start: usize, num: usize) {
+        if !self.rsyncable {
+            return;
+        }
+        for i in start..start + num {
+            self.rsync_sum += self.window[i] as u64;
+            if self.rsync_filled < self.rsync_win {
+                // The sum does not cover a whole window yet
+                self.rsync_filled += 1;
+                continue;
+            }
+            self.rsync_sum -= self.window[i - self.rsync_win] as u64;
+            if self.rsync_chunk_end.is_none() && self.rsync_sum.is_multiple_of(RSYNC_WIN as u64) {
+                self.rsync_chunk_end = Some(i);
+            }
+        }
+    }
+
+    /// True once strstart has passed the end of the current rsync chunk; the
+    /// block must then be flushed and padded to a byte boundary.
+    fn rsync_boundary(&mut self) -> bool {
+        match self.rsync_chunk_end {
+            Some(end) if self.strstart > end => {
+                self.rsync_chunk_end = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn flush_block_wrapper(&mut self, trees: &mut Trees, state: &mut GzipState, pad: bool, eof: bool) -> i64 {
         // if self.block_start >= 0 {
             let start = self.block_start;
             let end = self.strstart as i64;
@@ -322,11 +724,11 @@ impl Deflate {
                 if start>=0{
                     let buf = &self.window[start as usize..end as usize];
                     let stored_len = end - start;
-                    trees.flush_block(state, Some(buf), stored_len as u64, eof)
+                    trees.flush_block(state, Some(buf), stored_len as u64, pad, eof)
                 } else {
                     // let buf = &self.window[start..end];
                     let stored_len = end - start;
-                    trees.flush_block(state, None, stored_len as u64, eof)
+                    trees.flush_block(state, None, stored_len as u64, pad, eof)
                 }
                 
             } else {
@@ -346,7 +748,7 @@ impl Deflate {
         self.ins_h = self.update_hash(self.ins_h, self.window[s + MIN_MATCH - 1]);
         let ins_h = self.ins_h as usize;
         let match_head = self.head[ins_h] as usize;
-        self.prev[s & WMASK] = match_head as u16;
+        self.prev[s & self.w_mask] = match_head as u16;
         self.head[ins_h] = s as u16;
         match_head
     }
@@ -355,8 +757,8 @@ impl Deflate {
         let mut chain_length = self.max_chain_length; // Max hash chain length
         let scan = self.strstart;                     // Current string position
         let mut best_len = self.prev_length;          // Best match length so far
-        let limit = if self.strstart > MAX_DIST {
-            self.strstart - MAX_DIST
+        let limit = if self.strstart > self.max_dist {
+            self.strstart - self.max_dist
         } else {
             0
         };
@@ -369,12 +771,12 @@ impl Deflate {
             chain_length >>= 2;
         }
         assert!(
-            self.strstart <= WINDOW_SIZE - MIN_LOOKAHEAD,
+            self.strstart <= self.window_size - MIN_LOOKAHEAD,
             "insufficient lookahead"
         );
 
         let window = &self.window;
-        let window_size = WINDOW_SIZE;
+        let window_size = self.window_size;
         let mut nice_match = self.nice_match as usize;
 
         let strend = self.strstart + MAX_MATCH;
@@ -393,7 +795,7 @@ impl Deflate {
                 || window[match_index] != window[scan]
                 || window[match_index + 1] != window[scan + 1]
             {
-                cur_match = self.prev[cur_match & WMASK] as usize;
+                cur_match = self.prev[cur_match & self.w_mask] as usize;
                 if cur_match <= limit || chain_length == 0 {
                     break;
                 }
@@ -418,7 +820,7 @@ impl Deflate {
                 scan_end = window[scan + best_len];
             }
 
-            cur_match = self.prev[cur_match & WMASK] as usize;
+            cur_match = self.prev[cur_match & self.w_mask] as usize;
             if cur_match <= limit || chain_length == 0 {
                 break;
             }
@@ -1,9 +1,10 @@
 use std::io;
 use std::ptr::null_mut;
-use crate::GzipState;
+use std::rc::Rc;
+use crate::{Container, GzipState};
 use crate::trees::Trees;
-use crate::{OK, ERROR, STORED, WSIZE, INBUFSIZ};
-use std::io::{stdout, Read, Write, Cursor};
+use crate::{OK, STORED, WSIZE};
+use std::io::{stdout, Read};
 use std::cmp::min;
 use std::cmp::max;
 
@@ -146,6 +147,82 @@ pub struct Inflate {
     dbits: i32,
     hufts: u32,
     // slide: [u8; 2 * WSIZE],
+    pub damaged: bool,      // --recover skipped damaged data in this member
+    pub skip_trailer: bool, // --recover gave up on this member's trailer
+    pub limit_error: Option<io::Error>, // why an output limit stopped decoding (code 5)
+}
+
+/// Bytes --recover keeps ahead of the scan position, so that a candidate
+/// block can be decoded before it is accepted.
+const LOOKAHEAD: usize = 0x40000;
+
+/// Reader over the look-ahead bytes, to try decoding from a candidate
+/// position without consuming the real input.
+struct Lookahead {
+    data: Rc<[u8]>,
+    pos: usize,
+}
+
+impl Read for Lookahead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = min(buf.len(), self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Read n bits (n <= 16) starting at bit offset bit of data, least
+/// significant bit first. None past the end of data.
+fn peek_bits(data: &[u8], bit: usize, n: usize) -> Option<u32> {
+    let mut value = 0;
+    for i in 0..n {
+        let pos = bit + i;
+        let byte = *data.get(pos / 8)?;
+        value |= u32::from(byte >> (pos % 8) & 1) << i;
+    }
+    Some(value)
+}
+
+/// Whether a stored or dynamic block header plausibly starts at bit offset
+/// bit of data. Fixed blocks decode from almost any bits, so they are not
+/// considered.
+fn plausible_block(data: &[u8], bit: usize) -> bool {
+    match peek_bits(data, bit + 1, 2) {
+        Some(0) => {
+            // Zero padding up to a byte boundary, then LEN and its complement
+            let pad = (8 - (bit + 3) % 8) % 8;
+            if peek_bits(data, bit + 3, pad) != Some(0) {
+                return false;
+            }
+            let at = (bit + 3 + pad) / 8;
+            match data.get(at..at + 4) {
+                Some(len) => len[0] == !len[2] && len[1] == !len[3],
+                None => false,
+            }
+        }
+        Some(2) => {
+            let (Some(nl), Some(nd), Some(nb)) =
+                (peek_bits(data, bit + 3, 5), peek_bits(data, bit + 8, 5), peek_bits(data, bit + 13, 4))
+            else {
+                return false;
+            };
+            if nl > 29 || nd > 29 {
+                return false;
+            }
+            // The code length code must be complete
+            let mut kraft = 0;
+            for i in 0..nb as usize + 4 {
+                match peek_bits(data, bit + 17 + 3 * i, 3) {
+                    Some(0) => {}
+                    Some(len) => kraft += 1 << (7 - len),
+                    None => return false,
+                }
+            }
+            kraft == 128
+        }
+        _ => false,
+    }
 }
 
 impl Inflate {
@@ -158,77 +235,172 @@ impl Inflate {
             dbits: 6,
             hufts: 0,
             // slide: [0; 2 * WSIZE],
+            damaged: false,
+            skip_trailer: false,
+            limit_error: None,
         }
     }
 
-    pub fn fill_inbuf<R: Read>(&mut self, input: &mut R, eof_ok: bool, state: &mut GzipState) -> io::Result<u8> {
+    // Absolute position in the input of the next unused bit
+    fn bit_offset(&self, state: &GzipState) -> u64 {
+        state.input_pos() * 8 - u64::from(self.bk)
+    }
+
+    // --recover: after a failed block starting at bit block_start, scan the
+    // input for the next plausible block or gzip member and report the bytes
+    // skipped. Returns true when positioned on a block, false when the rest
+    // of the member is lost (the input is then at the next member or at its
+    // end).
+    fn resync(&mut self, state: &mut GzipState, block_start: u64) -> io::Result<bool> {
+        // Everything decoded before the damage is kept
+        if !self.flush_output(state, state.outcnt) {
+            return Ok(false);
+        }
+        self.damaged = true;
+        let damage_start = block_start / 8;
+
+        // Scan from just after the block start, as far back as the buffered
+        // input allows
+        let inbuf_base = state.bytes_in as u64 - state.insize as u64;
+        let start_bit = max(block_start + 1, inbuf_base * 8);
+        let mut base = start_bit / 8;
+        let mut bit = (start_bit % 8) as usize;
+        let mut data = state.inbuf[(base - inbuf_base) as usize..state.insize].to_vec();
+        data.append(&mut state.pending_input);
         state.insize = 0;
+        state.inptr = 0;
+        let mut eof = false;
+
         loop {
-            let len = self.read_buffer(input, state)?;
-            if len == 0 {
-                break;
+            while !eof && data.len() < LOOKAHEAD {
+                let mut chunk = vec![0; LOOKAHEAD - data.len()];
+                let n = match state.ifd.as_mut() {
+                    Some(ifd) => ifd.read(&mut chunk)?,
+                    None => 0,
+                };
+                eof = n == 0;
+                data.extend_from_slice(&chunk[..n]);
             }
-            state.insize += len;
-            if state.insize >= INBUFSIZ {
-                break;
+            let shared: Rc<[u8]> = data.as_slice().into();
+            // Leave room to decode a candidate, unless the input ends here
+            let limit = if eof { data.len() * 8 } else { (data.len() - LOOKAHEAD / 2) * 8 };
+
+            while bit < limit {
+                let at = bit / 8;
+                if bit.is_multiple_of(8) && state.container == Container::Gzip && data[at..].starts_with(&[0x1f, 0x8b, 8]) {
+                    // Let the caller start over with the next member
+                    state.report_damage(damage_start, base + at as u64);
+                    state.pending_input = data.split_off(at);
+                    state.bytes_in = (base + at as u64) as i64;
+                    self.bb = 0;
+                    self.bk = 0;
+                    return Ok(false);
+                }
+                if plausible_block(&data, bit) && self.try_block(state, &shared, bit) {
+                    state.report_damage(damage_start, base + at as u64);
+                    state.pending_input = data.split_off(at + 1);
+                    state.bytes_in = (base + at as u64 + 1) as i64;
+                    self.bb = u32::from(data[at]) >> (bit % 8);
+                    self.bk = 8 - (bit % 8) as u32;
+                    return Ok(true);
+                }
+                bit += 1;
             }
-        }
 
-        if state.insize == 0 {
-            if eof_ok {
-                return Ok(0xFF);
+            if eof {
+                state.report_damage(damage_start, base + data.len() as u64);
+                state.bytes_in = (base + data.len() as u64) as i64;
+                self.bb = 0;
+                self.bk = 0;
+                return Ok(false);
             }
-            self.flush_window(state)?;
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected EOF"));
+            let done = bit / 8;
+            data.drain(..done);
+            base += done as u64;
+            bit -= done * 8;
         }
-        state.bytes_in += state.insize as i64;
-        state.inptr = 1;
-        Ok(state.inbuf[0])
     }
 
-    pub fn read_buffer<R: Read>(&mut self, input: &mut R, state: &mut GzipState) -> io::Result<usize> {
-        let buffer = &mut state.inbuf[state.insize..INBUFSIZ];
-        let len = input.read(buffer)?;
+    // Decode the block at bit offset bit of data into a sink, leaving the
+    // state as it was apart from the window. True if the block decodes, or
+    // is still fine where data ends.
+    fn try_block(&mut self, state: &mut GzipState, data: &Rc<[u8]>, bit: usize) -> bool {
+        let ifd = state.ifd.replace(Box::new(Lookahead { data: data.clone(), pos: bit / 8 + 1 }));
+        let ofd = state.ofd.replace(Box::new(io::sink()));
+        let progress = state.progress.take();
+        let saved = (state.bytes_in, state.bytes_out, state.crc16_digest, state.adler);
+        // Trial output counts against no limit
+        let limits = (state.max_output.take(), state.max_ratio.take(), state.file_out, state.file_crc);
+        state.insize = 0;
+        state.inptr = 0;
+        state.outcnt = 0;
+        self.bb = u32::from(data[bit / 8]) >> (bit % 8);
+        self.bk = 8 - (bit % 8) as u32;
 
-        // Output the data read and its length for debugging
-        if len > 0 {
-            print!("Read {} bytes: ", len);
-            for byte in &buffer[..len] {
-                print!("{:02x} ", byte);
-            }
-            println!();
-        } else if len == 0 {
-            println!("Reached end of file");
-        }
+        let mut e = 0;
+        let r = self.inflate_block(&mut e, state);
 
-        Ok(len)
+        state.ifd = ifd;
+        state.ofd = ofd;
+        state.progress = progress;
+        (state.bytes_in, state.bytes_out, state.crc16_digest, state.adler) = saved;
+        (state.max_output, state.max_ratio, state.file_out, state.file_crc) = limits;
+        state.insize = 0;
+        state.inptr = 0;
+        state.outcnt = 0;
+        r == 0 || r == 4
     }
 
-    pub fn flush_window(&mut self, state: &mut GzipState) -> std::io::Result<()> {
-        // println!("flush: outcnt={:?}",state.outcnt);
-        if state.outcnt == 0 {
-            return Ok(());
-        }
-        // println!("flush: outcnt={:?}",state.outcnt);
-
-        state.updcrc(Some(&state.window.clone()), state.outcnt);
-
-        if !state.test {
-            state.ofd.as_mut().expect("REASON").write_all(&state.window[0..state.outcnt])?;
-//             state.write_buf(&mut state.ofd, &state.window[0..state.outcnt], state.outcnt);
+    // gzjoin: decode one deflate stream, returning the input bit offsets of
+    // the header of its last block and of the end of the stream, or the
+    // inflate error code. on_block is given the output offset of each block
+    // as it starts.
+    pub fn locate_last_block(
+        &mut self,
+        state: &mut GzipState,
+        mut on_block: impl FnMut(u64),
+    ) -> Result<(u64, u64), i32> {
+        state.outcnt = 0;
+        self.bk = 0;
+        self.bb = 0;
+        let mut e = 0;
+        loop {
+            on_block(state.bytes_out as u64 + state.outcnt as u64);
+            let block_start = self.bit_offset(state);
+            let r = self.inflate_block(&mut e, state);
+            if r != 0 {
+                return Err(r);
+            }
+            if e != 0 {
+                let end = self.bit_offset(state);
+                // Leave the input at the byte after the stream, as inflate does
+                while self.bk >= 8 {
+                    self.bk -= 8;
+                    state.inptr -= 1;
+                }
+                if !self.flush_output(state, state.outcnt) {
+                    return Err(5);
+                }
+                return Ok((block_start, end));
+            }
         }
+    }
 
-        state.bytes_out += state.outcnt as i64;
-        state.outcnt = 0;
-        Ok(())
+    pub fn flush_window(&mut self, state: &mut GzipState) -> std::io::Result<()> {
+        state.flush_window()
     }
 
-    // Function to flush output (equivalent to macro flush_output in C)
-    pub fn flush_output(&mut self, state: &mut GzipState, w: usize) {
-        unsafe {
-            state.outcnt = w;
+    // Function to flush output (equivalent to macro flush_output in C).
+    // Returns false once an output limit stops the decoding.
+    pub fn flush_output(&mut self, state: &mut GzipState, w: usize) -> bool {
+        state.outcnt = w;
+        match self.flush_window(state) {
+            Err(err) if err.kind() == io::ErrorKind::FileTooLarge => {
+                self.limit_error = Some(err);
+                false
+            }
+            _ => true,
         }
-        self.flush_window(state);
     }
 
     pub fn get_byte(&mut self, state: &mut GzipState) -> io::Result<u8> {
@@ -237,20 +409,24 @@ impl Inflate {
             state.inptr += 1;                // Increment the pointer
             Ok(byte)
         } else {
-            let mut input = Cursor::new(vec![0; 1]);
-            Ok(self.fill_inbuf(&mut input, true, state)?)
+            match state.fill_inbuf(true)? {
+                Some(byte) => Ok(byte),
+                None => {
+                    state.file_error("unexpected end of file");
+                    Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected EOF"))
+                }
+            }
         }
     }
 
-    // `try_byte()` function
-    pub fn try_byte(&mut self, state: &mut GzipState) -> io::Result<u8> {
+    // `try_byte()` function: None at the end of the input
+    pub fn try_byte(&mut self, state: &mut GzipState) -> io::Result<Option<u8>> {
         if state.inptr < state.insize {
             let byte = state.inbuf[state.inptr];  // Get the byte at the current pointer
             state.inptr += 1;                // Increment the pointer
-            Ok(byte)
+            Ok(Some(byte))
         } else {
-            let mut input = Cursor::new(vec![1; 1]);
-            Ok(self.fill_inbuf(&mut input, true, state)?)
+            state.fill_inbuf(true)
         }
     }
 
@@ -281,16 +457,18 @@ impl Inflate {
         self.Get_Byte(state, w)
     }
 
-    // Equivalent to the NEEDBITS macro (requiring more information to be fully accurate)
-    pub fn need_bits(&mut self, state: &mut GzipState,k: &mut u32, b: &mut u32, n: u32, w: usize)  {
+    // Equivalent to the NEEDBITS macro. Returns false when the input ends
+    // first; callers then fail with 4.
+    pub fn need_bits(&mut self, state: &mut GzipState,k: &mut u32, b: &mut u32, n: u32, w: usize) -> bool {
         while *k < n {
                 let byte = match self.next_byte(state, w) {
-                    Ok(value) => value, Err(_) => todo!(),
+                    Ok(value) => value, Err(_) => return false,
                 };
                 *b |= (u32::from(byte)) << *k;
 
                 *k += 8;
             }
+        true
     }
 
     // Equivalent to DUMPBITS macro
@@ -398,10 +576,10 @@ impl Inflate {
             y <<= 1;
         }
 
-        y -= c[g as usize];
-        if y < 0 {
-            return 2;
-        }
+        y = match y.checked_sub(c[g as usize]) {
+            Some(new_y) => new_y,
+            None => return 2, // over-subscribed
+        };
         c[g as usize] += y;
 
         // Generate starting offsets
@@ -699,7 +877,9 @@ impl Inflate {
 
         loop {
             // Get a literal/length code
-            self.need_bits(state, &mut k, &mut b, *bl as u32, w);
+            if !self.need_bits(state, &mut k, &mut b, *bl as u32, w) {
+                return 4;
+            }
             // let index = (b & ml) as usize;
             // println!("b={:?},ml={:?},b&ml={:?}",b, ml, index);
             // // println!("tl={:?}",tl);
@@ -738,7 +918,9 @@ impl Inflate {
                     e -= 16;
 
                     // 调用NEEDBITS函数
-                    self.need_bits(state, &mut k, &mut b, e as u32, w);
+                    if !self.need_bits(state, &mut k, &mut b, e as u32, w) {
+                        return 4;
+                    }
 
                     // 更新t和e
 
@@ -769,7 +951,9 @@ impl Inflate {
                 w += 1;
                 if w == WSIZE {
                     // println!("slide={:?}",self.slide);
-                    self.flush_output(state, w);
+                    if !self.flush_output(state, w) {
+                        return 5;
+                    }
                     w = 0;
                 }
             } else {
@@ -781,7 +965,9 @@ impl Inflate {
                 // println!("in e={:?}",e);
 
                 // Get length of block to copy
-                self.need_bits(state, &mut k, &mut b, e as u32, w);
+                if !self.need_bits(state, &mut k, &mut b, e as u32, w) {
+                    return 4;
+                }
                 let mut n = match t.v {
                     HuftValue::N(n) => n as usize + (b & mask_bits[e as usize]) as usize,
                     _ => panic!("Expected HuftValue::N, but found HuftValue::T"),
@@ -790,7 +976,9 @@ impl Inflate {
                 self.dump_bits(&mut k, &mut b, e as u32);
 
                 // Get distance of block to copy
-                self.need_bits(state, &mut k, &mut b, *bd as u32, w);
+                if !self.need_bits(state, &mut k, &mut b, *bd as u32, w) {
+                    return 4;
+                }
 
                 // let mut e;
                 // 计算初始的t
@@ -836,7 +1024,9 @@ impl Inflate {
                         e -= 16;
 
                         // 调用NEEDBITS函数
-                        self.need_bits(state, &mut k, &mut b, e as u32, w);
+                        if !self.need_bits(state, &mut k, &mut b, e as u32, w) {
+                            return 4;
+                        }
 
                         // 更新t和e
                         t = match &t.v {
@@ -855,7 +1045,9 @@ impl Inflate {
 
                 self.dump_bits(&mut k, &mut b, t.b as u32);
 
-                self.need_bits(state, &mut k, &mut b, e as u32, w);
+                if !self.need_bits(state, &mut k, &mut b, e as u32, w) {
+                    return 4;
+                }
                 d = match t.v {
                     HuftValue::N(n) => w as isize  - n as isize  - (b & mask_bits[e as usize]) as isize ,
                     _ => panic!("Expected HuftValue::N, but found HuftValue::T"),
@@ -906,7 +1098,9 @@ impl Inflate {
 
                     if w == WSIZE {
                         // println!("flushed!");
-                        self.flush_output(state, w);
+                        if !self.flush_output(state, w) {
+                            return 5;
+                        }
                         w = 0;
                     }
                 }
@@ -938,10 +1132,14 @@ impl Inflate {
         self.dump_bits(&mut k, &mut b, n);
 
         // get the length and its complement
-        self.need_bits(state, &mut k, &mut b, 16, w);
+        if !self.need_bits(state, &mut k, &mut b, 16, w) {
+            return 4;
+        }
         n = (b & 0xffff) as u32;
         self.dump_bits(&mut k, &mut b, 16);
-        self.need_bits(state, &mut k, &mut b, 16,w);
+        if !self.need_bits(state, &mut k, &mut b, 16,w) {
+            return 4;
+        }
 
         if n != (!b & 0xffff) as u32 {
             return 1;  // error in compressed data
@@ -950,12 +1148,16 @@ impl Inflate {
 
         // read and output the compressed data
         while n > 0 {
-            self.need_bits(state, &mut k, &mut b, 8, w);
+            if !self.need_bits(state, &mut k, &mut b, 8, w) {
+                return 4;
+            }
             state.window[w] = (b & 0xff) as u8;  // assuming slide is an array
             w += 1;
 
             if w == WSIZE {
-                self.flush_output(state, w);
+                if !self.flush_output(state, w) {
+                    return 5;
+                }
                 w = 0;
             }
             self.dump_bits(&mut k, &mut b, 8);
@@ -1036,8 +1238,10 @@ impl Inflate {
         }
 
         // Decompress until an end-of-block code
-        if self.inflate_codes(state, &mut tl, &mut td, &mut bl, &mut bd) != 0 {
-            return 1;
+        match self.inflate_codes(state, &mut tl, &mut td, &mut bl, &mut bd) {
+            0 => {}
+            4 => return 4,
+            _ => return 1,
         }
 
         // Free the decoding tables
@@ -1065,17 +1269,23 @@ impl Inflate {
         // println!("ib={:?}",state.inbuf);
 
         // Read table lengths
-        self.need_bits(state, &mut k, &mut b, 5, w as usize);
+        if !self.need_bits(state, &mut k, &mut b, 5, w as usize) {
+            return 4;
+        }
         let nl = 257 + (b & 0x1f); // Number of literal/length codes
         // println!("k={:?}, b={:?}",k,b);
         self.dump_bits(&mut k, &mut b, 5);
         // println!("k={:?}, b={:?}",k,b);
-        self.need_bits(state, &mut k, &mut b, 5, w as usize);
+        if !self.need_bits(state, &mut k, &mut b, 5, w as usize) {
+            return 4;
+        }
         // println!("k={:?}, b={:?}",k,b);
         let nd = 1 + (b & 0x1f);   // Number of distance codes
         self.dump_bits(&mut k, &mut b, 5);
         // println!("k={:?}, b={:?}",k,b);
-        self.need_bits(state, &mut k, &mut b, 4, w as usize);
+        if !self.need_bits(state, &mut k, &mut b, 4, w as usize) {
+            return 4;
+        }
         // println!("k={:?}, b={:?}",k,b);
         let nb = 4 + (b & 0xf);    // Number of bit length codes
         self.dump_bits(&mut k, &mut b, 4);
@@ -1088,7 +1298,9 @@ impl Inflate {
         // Build bit-length table
         let mut bit_lengths = vec![0u32; 19];
         for j in 0..nb {
-            self.need_bits(state, &mut k, &mut b, 3, w as usize);
+            if !self.need_bits(state, &mut k, &mut b, 3, w as usize) {
+                return 4;
+            }
             bit_lengths[border[j as usize] as usize] = b & 7;
             self.dump_bits(&mut k, &mut b, 3);
         }
@@ -1132,7 +1344,9 @@ impl Inflate {
         let mask = mask_bits[bl as usize];
 
         while i < n {
-            self.need_bits(state, &mut k, &mut b, bl as u32, w as usize);
+            if !self.need_bits(state, &mut k, &mut b, bl as u32, w as usize) {
+                return 4;
+            }
             let index = (b & mask) as usize;
 
             let entry = match tl.as_ref() {
@@ -1169,7 +1383,9 @@ impl Inflate {
                 literal_lengths[i as usize] = l;
                 i += 1;
             } else if j == 16 {
-                self.need_bits(state, &mut k, &mut b, 2, w as usize);
+                if !self.need_bits(state, &mut k, &mut b, 2, w as usize) {
+                    return 4;
+                }
                 let repeat = 3 + (b & 3);
                 self.dump_bits(&mut k, &mut b, 2);
                 if i + repeat > n {
@@ -1180,7 +1396,9 @@ impl Inflate {
                     i += 1;
                 }
             } else if j == 17 {
-                self.need_bits(state, &mut k, &mut b, 3, w as usize);
+                if !self.need_bits(state, &mut k, &mut b, 3, w as usize) {
+                    return 4;
+                }
                 let repeat = 3 + (b & 7);
                 self.dump_bits(&mut k, &mut b, 3);
                 if i + repeat > n {
@@ -1192,7 +1410,9 @@ impl Inflate {
                 }
                 l = 0;
             } else if j == 18 {
-                self.need_bits(state, &mut k, &mut b, 7, w as usize);
+                if !self.need_bits(state, &mut k, &mut b, 7, w as usize) {
+                    return 4;
+                }
                 let repeat = 11 + (b & 0x7f);
                 self.dump_bits(&mut k, &mut b, 7);
                 if i + repeat > n {
@@ -1272,10 +1492,10 @@ impl Inflate {
 
         // Decompress until an end-of-block code
         // println!("dynamic!");
-        let err = if self.inflate_codes(state, &mut tl, &mut td, &mut bl, &mut bd) > 0 {
-            1
-        } else {
-            0
+        let err = match self.inflate_codes(state, &mut tl, &mut td, &mut bl, &mut bd) {
+            0 => 0,
+            4 => 4,
+            _ => 1,
         };
 
         // Free decoding tables
@@ -1307,12 +1527,16 @@ impl Inflate {
         
 
         // Read the last block bit
-        self.need_bits(state, &mut k, &mut b, 1, w.try_into().unwrap());
+        if !self.need_bits(state, &mut k, &mut b, 1, w.try_into().unwrap()) {
+            return 4;
+        }
         *e = (b & 1) as i32;
         self.dump_bits(&mut k, &mut b, 1);
 
         // Read the block type
-        self.need_bits(state, &mut k, &mut b, 2, w.try_into().unwrap());
+        if !self.need_bits(state, &mut k, &mut b, 2, w.try_into().unwrap()) {
+            return 4;
+        }
         t = (b & 3) as u32;
         self.dump_bits(&mut k, &mut b, 2);
 
@@ -1347,9 +1571,21 @@ impl Inflate {
         loop {
             self.hufts = 0; // Initialize `hufts`
 
+            let block_start = self.bit_offset(state);
             r = self.inflate_block(&mut e, state);
             if r != 0 {
-                return r; // Return the error code
+                if !state.recover || r == 3 || r == 5 {
+                    return r; // Return the error code
+                }
+                match self.resync(state, block_start) {
+                    _ if self.limit_error.is_some() => return 5,
+                    Ok(true) => continue,
+                    Ok(false) => {
+                        self.skip_trailer = true;
+                        return 0;
+                    }
+                    Err(_) => return r,
+                }
             }
 
             if self.hufts > h {
@@ -1369,7 +1605,9 @@ impl Inflate {
             state.inptr -= 1; // Assume `inptr` is a global variable pointing to the input buffer
         }
 
-        self.flush_output(state, state.outcnt); // Assume `flush_output` is a function that writes decompressed data to the output
+        if !self.flush_output(state, state.outcnt) {
+            return 5;
+        }
 
         // Return success status
         // println!("{}", format!("<{}> ", h)); // Assume `trace` is a debugging output function
@@ -0,0 +1,194 @@
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+use crate::inflate::Inflate;
+use crate::{GzipState, ListSink, DEFLATED, ERROR, OK, OS_UNKNOWN, WARNING};
+
+// Header of a member put together from other members: no name, no time
+// stamp, unknown OS (as gzjoin)
+pub(crate) const BARE_HEADER: [u8; 10] = [0x1f, 0x8b, DEFLATED as u8, 0, 0, 0, 0, 0, 0, OS_UNKNOWN];
+
+/// Position of one gzip member in its input file.
+pub(crate) struct Member {
+    pub start: u64, // byte offset of the deflate data
+    pub last: u64,  // bit offset of the header of the last block
+    pub end: u64,   // bit offset of the end of the deflate data
+}
+
+/// Join the gzip files in names into a single member on stdout, without
+/// recompressing: each member is inflated only to find its last block and
+/// its end, then the deflate data is copied with the last-block flags of all
+/// but the final member cleared. Nothing is written unless every input is a
+/// valid gzip file.
+pub fn join(state: &mut GzipState, names: &[OsString]) -> io::Result<()> {
+    // CRC-32 register and length of all the data, as decoded
+    let totals = Rc::new(Cell::new((0xffffffff, 0)));
+    let mut inputs = Vec::new();
+    for name in names {
+        let path = Path::new(name);
+        match locate_members(state, path, &totals) {
+            Ok(Some(members)) => inputs.push((path, members)),
+            Ok(None) => {
+                state.exit_code = ERROR;
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("{}: {}: {}", state.program_name, path.display(), err);
+                state.exit_code = ERROR;
+                return Ok(());
+            }
+        }
+    }
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    out.write_all(&BARE_HEADER)?;
+    let count = inputs.iter().map(|(_, members)| members.len()).sum::<usize>();
+    let mut done = 0;
+    for (path, members) in &inputs {
+        let mut src = File::open(path)?;
+        for member in members {
+            done += 1;
+            splice(&mut src, &mut out, member, done == count)?;
+        }
+    }
+    let (crc, len) = totals.get();
+    out.write_all(&(crc ^ 0xffffffff).to_le_bytes())?;
+    out.write_all(&(len as u32).to_le_bytes())?;
+    out.flush()
+}
+
+// Inflate every member of path, checking its trailer, to find where its
+// deflate data and last block are. None once the problem has been reported;
+// warnings are passed on to state.
+fn locate_members(state: &mut GzipState, path: &Path, totals: &Rc<Cell<(u32, i64)>>) -> io::Result<Option<Vec<Member>>> {
+    let (mut reader, mut input) = open_reader(state, path, Box::new(ListSink(totals.clone())))?;
+    let mut members = Vec::new();
+    loop {
+        let method = reader.get_method(&mut input)?;
+        if method.is_none() && reader.method != -1 && !members.is_empty() {
+            // Trailing data, already dealt with as --trailing says
+            break;
+        }
+        if method != Some(DEFLATED) || reader.last_member {
+            if method.is_some() {
+                reader.file_error("not a gzip file");
+            }
+            return Ok(None);
+        }
+        match read_member(&mut reader, |_| {})? {
+            Some(member) => members.push(member),
+            None => return Ok(None),
+        }
+
+        if reader.input_eof()? {
+            break;
+        }
+    }
+    if reader.exit_code == ERROR {
+        return Ok(None);
+    }
+    if reader.exit_code == WARNING && state.exit_code == OK {
+        state.exit_code = WARNING;
+    }
+    Ok(Some(members))
+}
+
+// State to decode path with, passing the output to sink. Messages follow the
+// options of state.
+pub(crate) fn open_reader(state: &GzipState, path: &Path, sink: Box<dyn Write>) -> io::Result<(GzipState, File)> {
+    let input = File::open(path)?;
+    let mut reader = GzipState::new();
+    reader.decompress = true;
+    reader.to_stdout = true;
+    reader.quiet = state.quiet;
+    reader.trailing = state.trailing.clone();
+    reader.program_name = state.program_name.clone();
+    reader.ifname = path.to_path_buf();
+    reader.ifd = Some(Box::new(input.try_clone()?));
+    reader.ofd = Some(sink);
+    reader.clear_bufs();
+    Ok((reader, input))
+}
+
+// Inflate the deflate data of the member whose header get_method just read,
+// and check it against the trailer. on_block is given the output offset of
+// each block as it starts. None once a problem has been reported.
+pub(crate) fn read_member(reader: &mut GzipState, on_block: impl FnMut(u64)) -> io::Result<Option<Member>> {
+    let start = reader.input_pos();
+    let mut inflate = Inflate::new();
+    reader.updcrc(None, 0);
+    reader.bytes_out = 0;
+    let (last, end) = match inflate.locate_last_block(reader, on_block) {
+        Ok(offsets) => offsets,
+        Err(code) => {
+            reader.file_error(if code == 4 {
+                "unexpected end of file"
+            } else {
+                "invalid compressed data--format violated"
+            });
+            return Ok(None);
+        }
+    };
+
+    let mut trailer = [0u8; 8];
+    for byte in trailer.iter_mut() {
+        *byte = match inflate.try_byte(reader)? {
+            Some(byte) => byte,
+            None => {
+                reader.file_error("unexpected end of file");
+                return Ok(None);
+            }
+        };
+    }
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let len = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if crc != reader.updcrc(Some(&[]), 0) {
+        reader.file_error("invalid compressed data--crc error");
+        return Ok(None);
+    }
+    if len != reader.bytes_out as u32 {
+        reader.file_error("invalid compressed data--length error");
+        return Ok(None);
+    }
+    Ok(Some(Member { start, last, end }))
+}
+
+// Copy the deflate data of member to out. Unless it is the final member,
+// its last-block flag is cleared and an empty stored block brings the
+// output back to a byte boundary for the next one.
+fn splice<W: Write>(src: &mut File, out: &mut W, member: &Member, is_final: bool) -> io::Result<()> {
+    let last_byte = member.last / 8;
+    let end_byte = member.end.div_ceil(8);
+    let used_bits = member.end % 8;
+
+    src.seek(SeekFrom::Start(member.start))?;
+    let mut buf = vec![0u8; 0x10000];
+    let mut pos = member.start;
+    while pos < end_byte {
+        let n = (end_byte - pos).min(buf.len() as u64) as usize;
+        src.read_exact(&mut buf[..n])?;
+        if !is_final && (pos..pos + n as u64).contains(&last_byte) {
+            buf[(last_byte - pos) as usize] &= !(1 << (member.last % 8));
+        }
+        // Bits past the end of the stream must be zero: they are the header
+        // and padding of the stored block, or just padding
+        if used_bits != 0 && pos + n as u64 == end_byte {
+            buf[n - 1] &= (1 << used_bits) - 1;
+        }
+        out.write_all(&buf[..n])?;
+        pos += n as u64;
+    }
+
+    if !is_final && used_bits != 0 {
+        // The 3 zero bits of the stored block header spill into another
+        // byte when fewer are left in the last one
+        if used_bits > 5 {
+            out.write_all(&[0])?;
+        }
+        out.write_all(&[0, 0, 0xff, 0xff])?;
+    }
+    Ok(())
+}
@@ -3,23 +3,37 @@ mod zip;
 mod deflate;
 mod inflate;
 mod unzip;
+mod progress;
+mod join;
+mod append;
 
 use crate::zip::zip;
 use crate::unzip::unzip;
+use crate::join::join;
+use crate::append::append;
+use crate::progress::{print_progress, ProgressHook, ProgressMeter};
+use crate::deflate::{Strategy, DEF_MEM_LEVEL, MAX_MEM_LEVEL, MAX_WBITS, MIN_WBITS, ULTRA_LEVEL};
 use byteorder::{LittleEndian, ReadBytesExt};
 use chrono::{DateTime, Datelike, Local, Timelike};
 // use crc::{Crc, Digest, CRC_16_IBM_SDLC};
 use crc::{Crc, CRC_32_ISO_HDLC};
-use std::collections::HashSet;
+use filetime::FileTime;
+use std::cell::{Cell, RefCell};
+use std::ffi::{CString, OsStr, OsString};
 use std::fs::{File, Metadata};
-use std::io::{stdout, Read, Write};
+use std::io::{stdout, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::sync::atomic::AtomicUsize;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
 use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
 use std::time::{Duration, SystemTime};
 use std::{env, fs, io};
-use std::os::unix::fs::MetadataExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
 
 #[cfg(test)]
 mod tests;
@@ -29,9 +43,14 @@ const BITS: i32 = 16; // Assuming 16 bits
 const DEFLATED: i32 = 8;
 const OK: i32 = 0;
 const ERROR: i32 = 1;
+const WARNING: i32 = 2;
 const MAX_PATH_LEN: usize = 1024; // As defined in the C code
 const Z_SUFFIX: &str = ".gz";
+const ZLIB_SUFFIX: &str = ".zz";
+const RAW_SUFFIX: &str = ".deflate";
 const MAX_SUFFIX: usize = 30; // Assuming maximum suffix length
+// Suffixes of files that are already compressed, besides the current one
+const KNOWN_SUFFIXES: [&str; 7] = [".gz", ".z", ".taz", ".tgz", "-gz", "-z", "_z"];
 
 const VERSION: &str = "1.10"; // Assuming version 1.0, replace with actual version.
 
@@ -41,6 +60,7 @@ const OS_CODE: u8 = 0x0b;
 const OS_CODE: u8 = 0x07;
 #[cfg(all(not(all(target_os = "windows", target_pointer_width = "32")), not(target_os = "macos")))]
 const OS_CODE: u8 = 0x03;
+const OS_UNKNOWN: u8 = 0xff; // OS byte written by --reproducible
 const CRC_32_TAB: [u32; 256] = [
   0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076dc419,
   0x706af48f, 0xe963a535, 0x9e6495a3, 0x0edb8832, 0x79dcb8a4,
@@ -122,6 +142,8 @@ const ORIG_NAME: u8 = 0x08; // bit 3 set: original file name present
 const COMMENT: u8 = 0x10; // bit 4 set: file comment present
 const ENCRYPTED: u8 = 0x20; // bit 5 set: file is encrypted
 const RESERVED: u8 = 0xC0; // bits 6 and 7: reserved
+// zlib FLG byte
+const FDICT: u8 = 0x20; // bit 5 set: preset dictionary id follows
 const WSIZE: usize = 0x8000;
 const INBUFSIZ: usize = 0x8000;
 const OUTBUFSIZ: usize = 16384;
@@ -167,6 +189,163 @@ const HELP_MSG: &[&str] = &[
 ];
 static COUNT: AtomicUsize = AtomicUsize::new(0);
 
+// Name of the output file being written, to be removed if a signal kills us
+// before it is complete. Null while there is nothing to clean up.
+static REMOVE_OFNAME: AtomicPtr<libc::c_char> = AtomicPtr::new(ptr::null_mut());
+
+// Signals that remove the partial output before terminating the process
+const HANDLED_SIGNALS: [libc::c_int; 4] = [libc::SIGHUP, libc::SIGINT, libc::SIGPIPE, libc::SIGTERM];
+
+/// Options that have no short form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LongOpt {
+    PresumeInputTty,
+    Ultra,
+    Strategy,
+    WindowBits,
+    MemLevel,
+    Zlib,
+    Raw,
+    Dict,
+    Rsyncable,
+    Synchronous,
+    Progress,
+    Format,
+    Recover,
+    MaxOutput,
+    MaxRatio,
+    Verify,
+    Trailing,
+    TrailingDump,
+    Reproducible,
+    Join,
+    Append,
+}
+
+/// Key of an option: its short option character, or a long-only option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Opt {
+    Short(char),
+    Long(LongOpt),
+}
+
+impl Opt {
+    // Whether GNU gzip has this option too
+    fn is_gnu(self) -> bool {
+        match self {
+            Opt::Short(_) => true,
+            Opt::Long(option) => {
+                matches!(option, LongOpt::PresumeInputTty | LongOpt::Synchronous | LongOpt::Rsyncable)
+            }
+        }
+    }
+}
+
+// Long options: name, whether an argument is required, and the option it
+// stands for.
+const LONG_OPTIONS: &[(&str, bool, Opt)] = &[
+    ("ascii", false, Opt::Short('a')),
+    ("to-stdout", false, Opt::Short('c')),
+    ("stdout", false, Opt::Short('c')),
+    ("decompress", false, Opt::Short('d')),
+    ("uncompress", false, Opt::Short('d')),
+    ("force", false, Opt::Short('f')),
+    ("help", false, Opt::Short('h')),
+    ("keep", false, Opt::Short('k')),
+    ("list", false, Opt::Short('l')),
+    ("license", false, Opt::Short('L')),
+    ("no-name", false, Opt::Short('n')),
+    ("name", false, Opt::Short('N')),
+    ("-presume-input-tty", false, Opt::Long(LongOpt::PresumeInputTty)),
+    ("quiet", false, Opt::Short('q')),
+    ("silent", false, Opt::Short('q')),
+    ("synchronous", false, Opt::Long(LongOpt::Synchronous)),
+    ("progress", false, Opt::Long(LongOpt::Progress)),
+    ("format", true, Opt::Long(LongOpt::Format)),
+    ("recover", false, Opt::Long(LongOpt::Recover)),
+    ("max-output", true, Opt::Long(LongOpt::MaxOutput)),
+    ("max-ratio", true, Opt::Long(LongOpt::MaxRatio)),
+    ("verify", false, Opt::Long(LongOpt::Verify)),
+    ("trailing", true, Opt::Long(LongOpt::Trailing)),
+    ("trailing-dump", true, Opt::Long(LongOpt::TrailingDump)),
+    ("reproducible", false, Opt::Long(LongOpt::Reproducible)),
+    ("join", false, Opt::Long(LongOpt::Join)),
+    ("append", true, Opt::Long(LongOpt::Append)),
+    ("recursive", false, Opt::Short('r')),
+    ("suffix", true, Opt::Short('S')),
+    ("test", false, Opt::Short('t')),
+    ("verbose", false, Opt::Short('v')),
+    ("version", false, Opt::Short('V')),
+    ("fast", false, Opt::Short('1')),
+    ("best", false, Opt::Short('9')),
+    ("lzw", false, Opt::Short('Z')),
+    ("bits", true, Opt::Short('b')),
+    ("rsyncable", false, Opt::Long(LongOpt::Rsyncable)),
+    ("ultra", false, Opt::Long(LongOpt::Ultra)),
+    ("strategy", true, Opt::Long(LongOpt::Strategy)),
+    ("window-bits", true, Opt::Long(LongOpt::WindowBits)),
+    ("mem-level", true, Opt::Long(LongOpt::MemLevel)),
+    ("zlib", false, Opt::Long(LongOpt::Zlib)),
+    ("raw", false, Opt::Long(LongOpt::Raw)),
+    ("dict", true, Opt::Long(LongOpt::Dict)),
+];
+
+// Options allowed in the GZIP environment variable: they tune compression or
+// messages, but never change which files are touched or where output goes.
+const ENV_OPTIONS: &[Opt] = &[
+    Opt::Short('1'), Opt::Short('2'), Opt::Short('3'), Opt::Short('4'), Opt::Short('5'),
+    Opt::Short('6'), Opt::Short('7'), Opt::Short('8'), Opt::Short('9'),
+    Opt::Short('n'), Opt::Short('N'), Opt::Short('q'), Opt::Short('v'),
+    Opt::Long(LongOpt::Ultra), Opt::Long(LongOpt::Rsyncable),
+    Opt::Long(LongOpt::Synchronous), Opt::Long(LongOpt::Progress),
+];
+
+/// Stream format wrapped around the deflate data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Container {
+    /// gzip header and CRC-32/ISIZE trailer (RFC 1952).
+    #[default]
+    Gzip,
+    /// zlib header, optional dictionary id and Adler-32 trailer (RFC 1950).
+    Zlib,
+    /// Bare deflate data without header or trailer (RFC 1951).
+    Raw,
+}
+
+/// How list, test and verbose results are reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// The columns and messages of GNU gzip.
+    #[default]
+    Text,
+    /// One JSON object per line for each file, then one for the totals.
+    Json,
+}
+
+/// What to do with data after the last gzip member of a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Trailing {
+    /// Skip it without a word.
+    Ignore,
+    /// Skip it with the GNU gzip warning.
+    #[default]
+    Warn,
+    /// Fail the file.
+    Error,
+    /// Write it to the given file.
+    Dump(PathBuf),
+}
+
+/// Sums over the files reported with --format=json.
+#[derive(Default)]
+struct JsonTotals {
+    files: u64,
+    errors: u64,
+    damaged: u64, // recovered with data skipped
+    compressed: i64,
+    uncompressed: i64,
+}
+
 // The main state structure encapsulating all the global variables
 struct GzipState {
     // Options and flags
@@ -183,33 +362,57 @@ struct GzipState {
     list: bool,
     verbose: i32,
     quiet: bool,
+    synchronous: bool,
+    show_progress: bool,
+    recover: bool, // skip damaged data instead of failing the file
+    damaged: Vec<(u64, u64)>, // input ranges --recover skipped in the current file
+    max_output: Option<u64>, // most bytes a file may decompress to
+    max_ratio: Option<u64>,  // most output bytes per input byte
+    file_out: u64,           // output of the current file, checked against the limits
+    file_crc: u32,           // CRC-32 register of that output, over all its members
+    verify: bool, // decode the compressed output again and compare it with the input
+    trailing: Trailing,
+    trailing_offset: Option<u64>, // where data after the last member starts in the input
+    reproducible: bool,     // header fields depend on nothing but the options
+    source_date_epoch: u64, // latest time stamp --reproducible writes
+    join: bool,             // splice the input files into one member
+    append: Option<PathBuf>, // gzip file to add the input to
+    progress: Option<ProgressMeter>, // reports the work done on the current file
     do_lzw: bool,
     test: bool,
     _foreground: bool,
     // Program state
     program_name: String,
     _env: Option<String>,
-    args: Vec<String>,
+    args: Vec<OsString>,
     z_suffix: String,
     z_len: usize,
     exit_code: i32,
     maxbits: i32,
     method: i32,
     level: i32,
+    strategy: Strategy,
+    rsyncable: bool,
+    window_bits: u32,
+    mem_level: u32,
+    container: Container,
+    format: Format,
+    json_totals: JsonTotals,
+    file_error: Option<String>, // what went wrong with the current file
+    dictionary: Option<Vec<u8>>, // preset dictionary given with --dict
+    preset_dict: bool,           // the stream being decoded uses the dictionary
     save_orig_name: bool,
     last_member: bool,
     part_nb: i32,
     time_stamp: Option<SystemTime>,
     ifile_size: i64,
-    _caught_signals: HashSet<i32>,
-    _exiting_signal: Option<i32>,
-    _remove_ofname_fd: Option<i32>,
     pub bytes_in: i64,
     bytes_out: i64,
     total_in: i64,
     total_out: i64,
-    ifname: String,
-    ofname: String,
+    ifname: PathBuf,
+    ofname: PathBuf,
+    tmp_ofname: PathBuf, // file the output is written to until it is complete
     istat: Option<Metadata>,
     ifd: Option<Box<dyn Read>>,
     ofd: Option<Box<dyn Write>>,
@@ -221,15 +424,16 @@ struct GzipState {
     // Function pointer for the current operation
     work: Option<fn(&mut GzipState) -> io::Result<()>>,
     inbuf: [u8; INBUFSIZ + INBUF_EXTRA], // Input buffer
+    pending_input: Vec<u8>, // read ahead by --recover, to be read again first
     outbuf: [u8; OUTBUFSIZ + OUTBUF_EXTRA], // Output buffer
     window: [u8; 2 * WSIZE], // Output buffer
     crc: u32,
     crc16_digest: u32,
+    adler: u32,
     first_time: bool,
     record_io: bool,
     bi_buf: u16,
     bi_valid: u8,
-    first_call: bool,
 }
 
 // Implementation of the GzipState struct
@@ -237,13 +441,13 @@ impl GzipState {
 
 
     fn new() -> Self {
-        let program_name = env::args().next().unwrap_or_else(|| "gzip".to_string())
+        let program_name = env::args_os().next().map(|arg| arg.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "gzip".to_string())
             .split(".").next().unwrap()
             .split("/").last().unwrap()
             .split("\\").last().unwrap().to_string();
 
-        // Create the initial state
-        let mut state = GzipState {
+        GzipState {
             presume_input_tty: false,
             ascii: false,
             to_stdout: false,
@@ -257,6 +461,22 @@ impl GzipState {
             list: false,
             verbose: 0,
             quiet: false,
+            synchronous: false,
+            show_progress: false,
+            recover: false,
+            damaged: Vec::new(),
+            max_output: None,
+            max_ratio: None,
+            file_out: 0,
+            file_crc: 0xffffffff,
+            verify: false,
+            trailing: Trailing::Warn,
+            trailing_offset: None,
+            reproducible: false,
+            source_date_epoch: 0,
+            join: false,
+            append: None,
+            progress: None,
             do_lzw: false,
             test: false,
             _foreground: false,
@@ -269,20 +489,28 @@ impl GzipState {
             maxbits: BITS,
             method: DEFLATED,
             level: 6,
+            strategy: Strategy::Default,
+            rsyncable: false,
+            window_bits: MAX_WBITS,
+            mem_level: DEF_MEM_LEVEL,
+            container: Container::Gzip,
+            format: Format::Text,
+            json_totals: JsonTotals::default(),
+            file_error: None,
+            dictionary: None,
+            preset_dict: false,
             save_orig_name: false,
             last_member: false,
             part_nb: 0,
             time_stamp: None,
             ifile_size: -1,
-            _caught_signals: HashSet::new(),
-            _exiting_signal: None,
-            _remove_ofname_fd: None,
             bytes_in: 0,
             bytes_out: 0,
             total_in: 0,
             total_out: 0,
-            ifname: String::new(),
-            ofname: String::new(),
+            ifname: PathBuf::new(),
+            ofname: PathBuf::new(),
+            tmp_ofname: PathBuf::new(),
             istat: None,
             ifd: None,
             ofd: None,
@@ -293,36 +521,17 @@ impl GzipState {
             header_bytes: 0,
             work: None, // Function pointer will be set during runtime
             inbuf: [0; INBUFSIZ + INBUF_EXTRA],
+            pending_input: Vec::new(),
             outbuf: [0; OUTBUFSIZ + OUTBUF_EXTRA],
             window: [0; 2 * WSIZE],
             crc: 0x00000000,
             crc16_digest: 0x00000000,
-            first_time: false,
+            adler: 1,
+            first_time: true,
             record_io: false,
             bi_buf: 0,
             bi_valid: 0,
-            first_call: true,
-        };
-
-        // Handle GZIP environment variable
-        if let Ok(gzip_env) = env::var("GZIP") {
-            if !gzip_env.is_empty() {
-                // Check if the option is valid
-                if gzip_env.starts_with('-') && gzip_env.len() == 2 && 
-                   gzip_env.chars().nth(1).unwrap().is_ascii_digit() {
-                    // Valid compression level option
-                    eprintln!("gzip: warning: GZIP environment variable is deprecated; use an alias or script");
-                } else {
-                    // Invalid option
-                    eprintln!("{}: {}: option not valid in GZIP environment variable", 
-                             state.program_name, gzip_env);
-                    eprintln!("Try `gzip --help' for more information.");
-                    exit(ERROR);
-                }
-            }
         }
-
-        state
     }
 
     // Example method to set the 'work' function pointer based on the operation
@@ -339,9 +548,8 @@ impl GzipState {
     // Other methods to manipulate the state can be added here
     // Function to perform cleanup and exit
     fn do_exit(&self, exitcode: i32) -> ! {
-        // Perform any necessary cleanup here.
-        // In Rust, resources are automatically cleaned up when they go out of scope,
-        // so explicit cleanup may not be necessary unless using unsafe code or raw pointers.
+        // An output still registered at this point is incomplete
+        remove_partial_output();
 
         exit(exitcode);
     }
@@ -379,108 +587,303 @@ impl GzipState {
 
     // Function to parse command-line arguments
     fn parse_args(&mut self) {
-        let args: Vec<String> = env::args().collect();
-        let mut arg_iter = args.iter().skip(1).peekable();
-
-        while let Some(arg) = arg_iter.next() {
-            if arg.starts_with('-') && arg.len() > 1 {
-                for (i, c) in arg[1..].chars().enumerate() {
-                    match c {
-                        'a' => self.ascii = true,
-                        'c' => self.to_stdout = true,
-                        'd' => self.decompress = true,
-                        'f' => self.force += 1,
-                        'h' | 'H' => {
-                            self.help();
-                            self.do_exit(OK);
-                        }
-                        'k' => self.keep = true,
-                        'l' => {
-                            self.list = true;
-                            self.decompress = true;
-                            self.to_stdout = true;
-                        }
-                        'L' => {
-                            self.license();
-                            self.do_exit(OK);
-                        }
-                        'n' => {
-                            self.no_name = Some(true);
-                            self.no_time = Some(true);
-                        }
-                        'N' => {
-                            self.no_name = Some(false);
-                            self.no_time = Some(false);
-                        }
-                        'q' => {
-                            self.quiet = true;
-                            self.verbose = 0;
-                        }
-                        'r' => self.recursive = true,
-                        't' => {
-                            self.test = true;
-                            self.decompress = true;
-                            self.to_stdout = true;
-                        }
-                        'v' => {
-                            self.verbose += 1;
-                            self.quiet = false;
-                        }
-                        'V' => {
-                            self.version();
-                            self.do_exit(OK);
-                        }
-                        'Z' => self.do_lzw = true,
-                        '1'..='9' => self.level = c.to_digit(10).unwrap() as i32,
-                        // 需要参数的选项
-                        'b' | 'S' => {
-                            if i < arg[1..].len() - 1 {
-                                // 如果参数直接跟在选项后面
-                                let value = &arg[i+2..];
-                                match c {
-                                    'b' => {
-                                        self.maxbits = value.parse().unwrap_or_else(|_| {
-                                            eprintln!("{}: -b operand is not an integer", self.program_name);
-                                            self.try_help();
-                                        });
-                                    }
-                                    'S' => {
-                                        self.z_suffix = value.to_string();
-                                        self.z_len = self.z_suffix.len();
-                                    }
-                                    _ => unreachable!(),
-                                }
-                                break;
-                            } else if let Some(next_arg) = arg_iter.next() {
-                                // 如果参数在下一个参数中
-                                match c {
-                                    'b' => {
-                                        self.maxbits = next_arg.parse().unwrap_or_else(|_| {
-                                            eprintln!("{}: -b operand is not an integer", self.program_name);
-                                            self.try_help();
-                                        });
-                                    }
-                                    'S' => {
-                                        self.z_suffix = next_arg.to_string();
-                                        self.z_len = self.z_suffix.len();
-                                    }
-                                    _ => unreachable!(),
-                                }
-                            } else {
-                                eprintln!("{}: option requires an argument -- '{}'", self.program_name, c);
-                                self.try_help();
-                            }
-                        }
-                        _ => {
-                            eprintln!("{}: unknown option -- '{}'", self.program_name, c);
+        // Options from GZIP come first, so the command line overrides them
+        if let Ok(gzip_env) = env::var("GZIP") {
+            let env_args: Vec<OsString> = gzip_env.split_whitespace().map(OsString::from).collect();
+            if !env_args.is_empty() {
+                self.parse_options(&env_args, true);
+                if !self.quiet {
+                    eprintln!(
+                        "{}: warning: GZIP environment variable is deprecated; use an alias or script",
+                        self.program_name
+                    );
+                }
+            }
+        }
+
+        let args: Vec<OsString> = env::args_os().skip(1).collect();
+        self.parse_options(&args, false);
+    }
+
+    // Apply the options in args and collect the file names. Options from the
+    // GZIP environment variable are limited to ENV_OPTIONS.
+    fn parse_options(&mut self, args: &[OsString], from_env: bool) {
+        let mut arg_iter = args.iter();
+        let next_value = |arg_iter: &mut std::slice::Iter<OsString>| {
+            arg_iter.next().map(|value| value.to_string_lossy().into_owned())
+        };
+
+        while let Some(os_arg) = arg_iter.next() {
+            // Anything that is not an option, including "-" for standard input
+            // and names that are not valid UTF-8, is a file name
+            let arg = match os_arg.to_str() {
+                Some(arg) if arg.starts_with('-') && arg.len() > 1 => arg,
+                _ if from_env => {
+                    eprintln!(
+                        "{}: {}: non-option in GZIP environment variable",
+                        self.program_name,
+                        os_arg.to_string_lossy()
+                    );
+                    self.try_help();
+                }
+                _ => {
+                    self.args.push(os_arg.clone());
+                    continue;
+                }
+            };
+            if arg == "--" {
+                if from_env {
+                    self.env_option_error("--");
+                }
+                // End of options: everything else is a file name
+                self.args.extend(arg_iter.cloned());
+                break;
+            }
+            if arg == "-11" {
+                self.set_option(Opt::Long(LongOpt::Ultra), None);
+                continue;
+            }
+            if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline_value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let (full_name, takes_arg, key) = self.lookup_long_option(name);
+                if from_env && !ENV_OPTIONS.contains(&key) {
+                    self.env_option_error(&format!("--{}", full_name));
+                }
+                let value = if takes_arg {
+                    match inline_value.or_else(|| next_value(&mut arg_iter)) {
+                        Some(value) => Some(value),
+                        None => {
+                            eprintln!("{}: option '--{}' requires an argument", self.program_name, full_name);
                             self.try_help();
                         }
                     }
+                } else if inline_value.is_some() {
+                    eprintln!("{}: option '--{}' doesn't allow an argument", self.program_name, full_name);
+                    self.try_help();
+                } else {
+                    None
+                };
+                self.set_option(key, value);
+                continue;
+            }
+            for (i, c) in arg[1..].char_indices() {
+                if from_env && !ENV_OPTIONS.contains(&Opt::Short(c)) {
+                    self.env_option_error(&format!("-{}", c));
+                }
+                if c == 'b' || c == 'S' {
+                    // The operand is the rest of the cluster or the next argument
+                    let rest = &arg[1 + i + c.len_utf8()..];
+                    let value = if !rest.is_empty() {
+                        rest.to_string()
+                    } else if let Some(next_arg) = next_value(&mut arg_iter) {
+                        next_arg
+                    } else {
+                        eprintln!("{}: option requires an argument -- '{}'", self.program_name, c);
+                        self.try_help();
+                    };
+                    self.set_option(Opt::Short(c), Some(value));
+                    break;
+                }
+                self.set_option(Opt::Short(c), None);
+            }
+        }
+    }
+
+    fn env_option_error(&self, option: &str) -> ! {
+        eprintln!("{}: {}: option not valid in GZIP environment variable", self.program_name, option);
+        self.try_help();
+    }
+
+    // Resolve a long option name, which may be abbreviated to any unambiguous
+    // prefix, to its full name, whether it takes an argument, and its key.
+    fn lookup_long_option(&self, name: &str) -> (&'static str, bool, Opt) {
+        if let Some(&option) = LONG_OPTIONS.iter().find(|option| option.0 == name) {
+            return option;
+        }
+        let mut candidates: Vec<&(&str, bool, Opt)> = LONG_OPTIONS
+            .iter()
+            .filter(|option| !name.is_empty() && option.0.starts_with(name))
+            .collect();
+        // Abbreviations that GNU gzip accepts mean the same here: the options
+        // it lacks only get the prefixes it leaves free
+        if candidates.iter().any(|option| option.2.is_gnu()) {
+            candidates.retain(|option| option.2.is_gnu());
+        }
+        match candidates.first() {
+            None => {
+                eprintln!("{}: unrecognized option '--{}'", self.program_name, name);
+                self.try_help();
+            }
+            // Several names for the same option are not ambiguous
+            Some(&&first) if candidates.iter().all(|option| option.2 == first.2) => first,
+            Some(_) => {
+                let possibilities: Vec<String> =
+                    candidates.iter().map(|option| format!("'--{}'", option.0)).collect();
+                eprintln!(
+                    "{}: option '--{}' is ambiguous; possibilities: {}",
+                    self.program_name,
+                    name,
+                    possibilities.join(" ")
+                );
+                self.try_help();
+            }
+        }
+    }
+
+    // Apply one option.
+    fn set_option(&mut self, key: Opt, value: Option<String>) {
+        let value = value.unwrap_or_default();
+        match key {
+            Opt::Short('a') => self.ascii = true,
+            Opt::Short('b') => {
+                self.maxbits = value.parse().unwrap_or_else(|_| {
+                    eprintln!("{}: -b operand is not an integer", self.program_name);
+                    self.try_help();
+                });
+            }
+            Opt::Short('c') => self.to_stdout = true,
+            Opt::Short('d') => self.decompress = true,
+            Opt::Short('f') => self.force += 1,
+            Opt::Short('h' | 'H') => {
+                self.help();
+                self.do_exit(OK);
+            }
+            Opt::Short('k') => self.keep = true,
+            Opt::Short('l') => {
+                self.list = true;
+                self.decompress = true;
+                self.to_stdout = true;
+            }
+            Opt::Short('L') => {
+                self.license();
+                self.do_exit(OK);
+            }
+            Opt::Short('n') => {
+                self.no_name = Some(true);
+                self.no_time = Some(true);
+            }
+            Opt::Short('N') => {
+                self.no_name = Some(false);
+                self.no_time = Some(false);
+            }
+            Opt::Short('q') => {
+                self.quiet = true;
+                self.verbose = 0;
+            }
+            Opt::Short('r') => self.recursive = true,
+            Opt::Short('S') => {
+                self.z_suffix = value;
+                self.z_len = self.z_suffix.len();
+            }
+            Opt::Short('t') => {
+                self.test = true;
+                self.decompress = true;
+                self.to_stdout = true;
+            }
+            Opt::Short('v') => {
+                self.verbose += 1;
+                self.quiet = false;
+            }
+            Opt::Short('V') => {
+                self.version();
+                self.do_exit(OK);
+            }
+            Opt::Short('Z') => self.do_lzw = true,
+            Opt::Short(c @ '1'..='9') => self.level = c.to_digit(10).unwrap() as i32,
+            Opt::Long(LongOpt::PresumeInputTty) => self.presume_input_tty = true,
+            Opt::Long(LongOpt::Rsyncable) => self.rsyncable = true,
+            Opt::Long(LongOpt::Synchronous) => self.synchronous = true,
+            Opt::Long(LongOpt::Progress) => self.show_progress = true,
+            Opt::Long(LongOpt::Recover) => self.recover = true,
+            Opt::Long(LongOpt::Verify) => self.verify = true,
+            Opt::Long(LongOpt::Reproducible) => self.reproducible = true,
+            Opt::Long(LongOpt::Join) => self.join = true,
+            Opt::Long(LongOpt::Append) => self.append = Some(PathBuf::from(value)),
+            Opt::Long(LongOpt::Trailing) => {
+                let trailing = match value.as_str() {
+                    "ignore" => Trailing::Ignore,
+                    "warn" => Trailing::Warn,
+                    "error" => Trailing::Error,
+                    _ => {
+                        eprintln!("{}: invalid --trailing action '{}'", self.program_name, value);
+                        self.try_help();
+                    }
+                };
+                self.set_trailing(trailing);
+            }
+            Opt::Long(LongOpt::TrailingDump) => self.set_trailing(Trailing::Dump(PathBuf::from(value))),
+            Opt::Long(LongOpt::MaxOutput) => {
+                let limit = parse_size(&value).unwrap_or_else(|| {
+                    eprintln!("{}: invalid --max-output size '{}'", self.program_name, value);
+                    self.try_help();
+                });
+                self.set_max_output(Some(limit));
+            }
+            Opt::Long(LongOpt::MaxRatio) => {
+                let limit = match value.parse::<u64>() {
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        eprintln!("{}: invalid --max-ratio '{}'", self.program_name, value);
+                        self.try_help();
+                    }
+                };
+                self.set_max_ratio(Some(limit));
+            }
+            Opt::Long(LongOpt::Ultra) => self.level = ULTRA_LEVEL,
+            Opt::Long(LongOpt::Format) => {
+                self.format = match value.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    _ => {
+                        eprintln!("{}: invalid format '{}'", self.program_name, value);
+                        self.try_help();
+                    }
+                };
+            }
+            Opt::Long(LongOpt::Strategy) => {
+                self.strategy = Strategy::from_name(&value).unwrap_or_else(|| {
+                    eprintln!("{}: invalid strategy '{}'", self.program_name, value);
+                    self.try_help();
+                });
+            }
+            Opt::Long(LongOpt::WindowBits) | Opt::Long(LongOpt::MemLevel) => {
+                let (name, range) = if key == Opt::Long(LongOpt::WindowBits) {
+                    ("--window-bits", MIN_WBITS..=MAX_WBITS)
+                } else {
+                    ("--mem-level", 1..=MAX_MEM_LEVEL)
+                };
+                let n = match value.parse::<u32>() {
+                    Ok(n) if range.contains(&n) => n,
+                    _ => {
+                        eprintln!(
+                            "{}: {} must be between {} and {}",
+                            self.program_name, name, range.start(), range.end()
+                        );
+                        self.try_help();
+                    }
+                };
+                if key == Opt::Long(LongOpt::WindowBits) {
+                    self.window_bits = n;
+                } else {
+                    self.mem_level = n;
                 }
-            } else {
-                self.args.push(arg.clone());
             }
-    
+            Opt::Long(LongOpt::Zlib) => self.container = Container::Zlib,
+            Opt::Long(LongOpt::Raw) => self.container = Container::Raw,
+            Opt::Long(LongOpt::Dict) => match fs::read(&value) {
+                Ok(dict) => self.dictionary = Some(dict),
+                Err(err) => {
+                    eprintln!("{}: {}: {}", self.program_name, value, err);
+                    self.do_exit(ERROR);
+                }
+            },
+            Opt::Short(c) => {
+                eprintln!("{}: unknown option -- '{}'", self.program_name, c);
+                self.try_help();
+            }
         }
     }
 
@@ -503,6 +906,33 @@ impl GzipState {
             self.no_name = Some(self.decompress);
         }
 
+        // --reproducible: no name, and no time stamp past SOURCE_DATE_EPOCH
+        if self.reproducible && !self.decompress {
+            self.no_name = Some(true);
+            if let Some(value) = env::var_os("SOURCE_DATE_EPOCH") {
+                let value = value.to_string_lossy();
+                self.source_date_epoch = value.trim().parse().unwrap_or_else(|_| {
+                    eprintln!("{}: invalid SOURCE_DATE_EPOCH '{}'", self.program_name, value);
+                    self.do_exit(ERROR);
+                });
+            }
+        }
+
+        // zlib and raw streams get their own default suffix
+        if self.z_suffix == Z_SUFFIX {
+            match self.container {
+                Container::Gzip => {}
+                Container::Zlib => self.z_suffix = ZLIB_SUFFIX.to_string(),
+                Container::Raw => self.z_suffix = RAW_SUFFIX.to_string(),
+            }
+            self.z_len = self.z_suffix.len();
+        }
+
+        if self.dictionary.is_some() && self.container == Container::Gzip {
+            eprintln!("{}: --dict requires --zlib or --raw", self.program_name);
+            self.try_help();
+        }
+
         if self.z_len == 0 || self.z_len > MAX_SUFFIX {
             eprintln!("{}: invalid suffix '{}'", self.program_name, self.z_suffix);
             self.do_exit(ERROR);
@@ -514,6 +944,40 @@ impl GzipState {
         // Install signal handlers (if necessary)
         self.install_signal_handlers();
 
+        // A status line only makes sense on a terminal
+        if self.show_progress && atty::is(atty::Stream::Stderr) {
+            self.set_progress_hook(Box::new(print_progress));
+        }
+
+        // --join writes a single member from all the files to stdout
+        if self.join {
+            if self.args.is_empty() || self.decompress || self.container != Container::Gzip {
+                eprintln!("{}: --join needs gzip files to join", self.program_name);
+                self.try_help();
+            }
+            if self.force == 0 && atty::is(atty::Stream::Stdout) {
+                if !self.quiet {
+                    eprintln!(
+                        "{}: compressed data not written to a terminal. Use -f to force compression.\nFor help, type: {} -h",
+                        self.program_name, self.program_name
+                    );
+                }
+                self.do_exit(ERROR);
+            }
+            join(self, &self.args.clone())?;
+            self.do_exit(self.exit_code);
+        }
+
+        // --append continues the last member of a gzip file with the input
+        if let Some(target) = self.append.clone() {
+            if self.join || self.decompress || self.container != Container::Gzip {
+                eprintln!("{}: --append needs data to add to a gzip file", self.program_name);
+                self.try_help();
+            }
+            append(self, &target, &self.args.clone())?;
+            self.do_exit(self.exit_code);
+        }
+
         // Process files
         if !self.args.is_empty() {
             if self.to_stdout && !self.test && !self.list && (!self.decompress || !self.ascii) {
@@ -521,31 +985,38 @@ impl GzipState {
                 // In Rust, stdout is typically in binary mode
             }
             for filename in self.args.clone() {
-                self.treat_file(&filename)?;
+                if let Err(err) = self.treat_file(Path::new(&filename)) {
+                    remove_partial_output();
+                    return Err(err);
+                }
             }
         } else {
             // Process standard input
             self.treat_stdin()?;
         }
 
-        if self.list && !self.quiet && self.args.len() > 1 {
-            self.do_list::<File>(None, -1)?; // Print totals
+        if self.json_records() {
+            self.write_json_totals();
+        } else if self.list && !self.quiet && self.args.len() > 1 {
+            self.do_list(None, -1)?; // Print totals
         }
 
         self.do_exit(self.exit_code);
     }
 
     // Placeholder for treat_file function
-    fn treat_file(&mut self, iname: &str) -> io::Result<()> {
-        if iname == "-" {
+    fn treat_file(&mut self, iname: &Path) -> io::Result<()> {
+        if iname.as_os_str() == "-" {
             let cflag = self.to_stdout;
             self.treat_stdin()?; // Assume treat_stdin is implemented
             self.to_stdout = cflag;
             return Ok(());
         }
 
-        let path = Path::new(iname);
-        self.ifname = iname.to_string();
+        let path = iname;
+        self.ifname = iname.to_path_buf();
+        self.file_error = None;
+        self.damaged.clear();
 
         let metadata = match fs::metadata(path) {
             Ok(meta) => meta,
@@ -555,7 +1026,8 @@ impl GzipState {
                     io::ErrorKind::PermissionDenied => "Permission denied",
                     _ => "unknown error"
                 };
-                eprintln!("{}: {}: {}", self.program_name, iname, error_msg);
+                eprintln!("{}: {}: {}", self.program_name, iname.display(), error_msg);
+                self.exit_code = ERROR;
                 return Ok(());
             }
         };
@@ -567,7 +1039,7 @@ impl GzipState {
                 // Warning: ifname is now invalid
                 return Ok(());
             } else {
-                eprintln!("{}: {} is a directory -- ignored", self.program_name, self.ifname);
+                eprintln!("{}: {} is a directory -- ignored", self.program_name, self.ifname.display());
                 return Ok(());
             }
         }
@@ -576,7 +1048,7 @@ impl GzipState {
             if !metadata.is_file() {
                 eprintln!(
                     "{}: {} is not a directory or a regular file -- ignored",
-                    self.program_name, self.ifname
+                    self.program_name, self.ifname.display()
                 );
                 return Ok(());
             }
@@ -589,14 +1061,14 @@ impl GzipState {
                 if (mode & 0o4000) != 0 {
                     eprintln!(
                         "{}: {} is set-user-ID on execution -- ignored",
-                        self.program_name, self.ifname
+                        self.program_name, self.ifname.display()
                     );
                     return Ok(());
                 }
                 if (mode & 0o2000) != 0 {
                     eprintln!(
                         "{}: {} is set-group-ID on execution -- ignored",
-                        self.program_name, self.ifname
+                        self.program_name, self.ifname.display()
                     );
                     return Ok(());
                 }
@@ -605,7 +1077,7 @@ impl GzipState {
                     if (mode & 0o1000) != 0 {
                         eprintln!(
                             "{}: {} has the sticky bit set -- file ignored",
-                            self.program_name, self.ifname
+                            self.program_name, self.ifname.display()
                         );
                         return Ok(());
                     }
@@ -614,7 +1086,7 @@ impl GzipState {
                         eprintln!(
                             "{}: {} has {} other link{} -- unchanged",
                             self.program_name,
-                            self.ifname,
+                            self.ifname.display(),
                             other_links,
                             if other_links == 1 { "" } else { "s" }
                         );
@@ -630,12 +1102,14 @@ impl GzipState {
             -1
         };
 
-        if !self.no_time.unwrap_or(false) || self.list {
-            self.time_stamp = metadata.modified().ok();
-        }
+        self.time_stamp = if !self.no_time.unwrap_or(false) || self.list {
+            metadata.modified().ok()
+        } else {
+            None
+        };
 
         if self.to_stdout && !self.list && !self.test {
-            self.ofname = "stdout".to_string();
+            self.ofname = PathBuf::from("stdout");
         } else if self.make_ofname().is_err() {
             return Ok(());
         }
@@ -655,6 +1129,9 @@ impl GzipState {
             self.method = match self.get_method(&mut ifd)? {
                 Some(method) => method,
                 None => {
+                    if self.json_records() {
+                        self.json_record(-1, None, Some(self.ifile_size), None);
+                    }
                     return Ok(());
                 }
             };
@@ -665,24 +1142,35 @@ impl GzipState {
             return Ok(());
         }
 
-        if self.to_stdout {
+        // Second handle on the output file, to sync it with --synchronous
+        let mut sync_fd = None;
+        if self.test {
+            self.ofd = Some(Box::new(io::sink()));
+        } else if self.to_stdout {
             self.ofd = Some(Box::new(io::stdout()));
         } else {
-            self.ofd = Some(Box::new(self.create_outfile()?));
+            let outfile = self.create_outfile()?;
+            if self.synchronous {
+                sync_fd = Some(outfile.try_clone()?);
+            }
+            self.ofd = Some(Box::new(outfile));
             if !self.decompress && self.save_orig_name && self.verbose == 0 && !self.quiet {
                 println!(
                     "{}: {} compressed to {}",
-                    self.program_name, self.ifname, self.ofname
+                    self.program_name, self.ifname.display(), self.ofname.display()
                 );
             }
         }
 
+        let verify_copy = self.tee_for_verify();
+
         if !self.save_orig_name {
             self.save_orig_name = !self.no_name.unwrap_or(false);
         }
 
-        if self.verbose != 0 {
-            eprint!("{}:\t", self.ifname);
+        // With a progress line, the name is printed once the line is complete
+        if self.verbose != 0 && self.format == Format::Text && self.progress.is_none() {
+            eprint!("{}:\t", self.ifname.display());
         }
 
         loop {
@@ -705,12 +1193,27 @@ impl GzipState {
                 Some(method) => method,
                 None => break,
             };
-            println!("method: {}", self.method);
             self.bytes_out = 0;
         }
 
+        self.report_progress(true);
+        if self.verbose != 0 && self.format == Format::Text && self.progress.is_some() {
+            eprint!("{}:\t", self.ifname.display());
+        }
+
         drop(ifd);
 
+        if self.method == -1 {
+            if !self.to_stdout {
+                self.remove_output_file()?;
+            }
+            if self.json_records() {
+                let compressed = if self.decompress { self.bytes_in } else { self.bytes_out };
+                self.json_record(-1, None, Some(compressed), None);
+            }
+            return Ok(());
+        }
+
         if !self.to_stdout {
             self.copy_stat()?;
 
@@ -719,22 +1222,61 @@ impl GzipState {
                     eprintln!("{}: write error: {}", self.program_name, err);
                 }
             }
-
-            if !self.keep {
-                if let Err(err) = fs::remove_file(path) {
-                    eprintln!("{}: {}", self.program_name, err);
-                }
-            }
         }
 
-        if self.method == -1 {
+        // A file that fails --verify keeps its input and loses its output
+        if !self.verify_compressed(verify_copy)? {
             if !self.to_stdout {
                 self.remove_output_file()?;
             }
+            if self.json_records() {
+                self.json_file_done();
+            }
             return Ok(());
         }
 
-        if self.verbose != 0 {
+        if !self.to_stdout {
+
+            // The input must not go away before the output is on disk
+            if let Some(ref outfile) = sync_fd {
+                if let Err(err) = outfile.sync_all() {
+                    eprintln!("{}: {}: {}", self.program_name, self.ofname.display(), err);
+                    self.exit_code = ERROR;
+                    self.remove_output_file()?;
+                    return Ok(());
+                }
+            }
+
+            // Readers only ever see the complete output under its real name
+            if let Err(err) = fs::rename(&self.tmp_ofname, &self.ofname) {
+                eprintln!("{}: {}: {}", self.program_name, self.ofname.display(), err);
+                self.exit_code = ERROR;
+                self.remove_output_file()?;
+                return Ok(());
+            }
+
+            // Complete: from here on an interruption must leave the output
+            set_remove_ofname(None);
+
+            if sync_fd.is_some() {
+                if let Err(err) = self.sync_output_dir() {
+                    eprintln!("{}: {}: {}", self.program_name, self.ofname.display(), err);
+                    self.exit_code = ERROR;
+                    return Ok(());
+                }
+            }
+
+            // A damaged input is kept: it may still yield more with other tools
+            if !self.keep && self.damaged.is_empty() {
+                if let Err(err) = fs::remove_file(path) {
+                    eprintln!("{}: {}", self.program_name, err);
+                }
+            }
+        }
+
+        if self.json_records() {
+            self.json_file_done();
+        } else if self.verbose != 0 {
             if self.test {
                 eprint!(" OK");
             } else if self.decompress {
@@ -749,7 +1291,7 @@ impl GzipState {
                 );
             }
             if !self.test && !self.to_stdout {
-                eprint!(" -- replaced with {}", self.ofname);
+                eprint!(" -- replaced with {}", self.ofname.display());
             }
             eprintln!();
         }
@@ -760,57 +1302,47 @@ impl GzipState {
         // Attempt to read the directory entries
         let dir_entries = match fs::read_dir(dir) {
             Ok(entries) => entries,
-            Err(_) => {
-                self.progerror(dir);
+            Err(err) => {
+                eprintln!("{}: {}: {}", self.program_name, dir.display(), err);
+                self.exit_code = ERROR;
                 return Ok(());
             }
         };
 
-        // Iterate over the directory entries
+        // Sort the entries so that the traversal order is reproducible
+        let mut entries = Vec::new();
         for entry_result in dir_entries {
-            let entry = match entry_result {
-                Ok(e) => e,
-                Err(_) => {
-                    self.progerror(dir);
-                    continue;
+            match entry_result {
+                Ok(entry) => entries.push(entry),
+                Err(err) => {
+                    eprintln!("{}: {}: {}", self.program_name, dir.display(), err);
+                    self.exit_code = ERROR;
                 }
-            };
-
-            let file_name = entry.file_name();
-            let file_name_str = file_name.to_string_lossy();
-
-            // Skip "." and ".." entries
-            if file_name_str == "." || file_name_str == ".." {
-                continue;
             }
+        }
+        entries.sort_by_key(|entry| entry.file_name());
 
-            let dir_str = dir.to_string_lossy();
-            let len = dir_str.len();
-            let entrylen = file_name_str.len();
-
-            // Check if the combined path length is within limits
-            if len + entrylen < MAX_PATH_LEN - 2 {
-                let mut nbuf = PathBuf::from(dir);
+        for entry in entries {
+            let nbuf = entry.path();
 
-                // On some systems, an empty `dir` means the current directory
-                if !dir_str.is_empty() {
-                    nbuf.push(&file_name);
-                } else {
-                    nbuf = PathBuf::from(&file_name);
+            // Symbolic links to directories are not followed, which also
+            // keeps the traversal from looping
+            let is_link = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+            if is_link && nbuf.is_dir() {
+                if self.verbose > 0 {
+                    eprintln!(
+                        "{}: {} is a symbolic link to a directory -- ignored",
+                        self.program_name,
+                        nbuf.display()
+                    );
                 }
+                continue;
+            }
 
-                // Call treat_file with the new path
-                if let Err(e) = self.treat_file(nbuf.to_str().unwrap()) {
-                    eprintln!("Error processing file {}: {}", nbuf.display(), e);
-                    self.exit_code = ERROR;
-                }
-            } else {
-                eprintln!(
-                    "{}: {}/{}: pathname too long",
-                    self.program_name,
-                    dir.display(),
-                    file_name_str
-                );
+            // A failure on one entry must not stop the rest of the tree
+            if let Err(e) = self.treat_file(&nbuf) {
+                remove_partial_output();
+                eprintln!("{}: {}: {}", self.program_name, nbuf.display(), e);
                 self.exit_code = ERROR;
             }
         }
@@ -833,40 +1365,59 @@ impl GzipState {
             self.do_exit(ERROR);
         }
 
-        self.ifname = "stdin".to_string();
-        self.ofname = "stdout".to_string();
+        self.ifname = PathBuf::from("stdin");
+        self.ofname = PathBuf::from("stdout");
+        self.file_error = None;
+        self.damaged.clear();
 
         self.ifile_size = -1;
 
-        if !self.no_time.unwrap_or(false) || self.list {
-            self.time_stamp = Some(SystemTime::now());
-        }
+        self.time_stamp = if !self.no_time.unwrap_or(false) || self.list {
+            Some(SystemTime::now())
+        } else {
+            None
+        };
 
         self.clear_bufs();
         self.to_stdout = true;
         self.part_nb = 0;
 
         let mut stdin = io::stdin();
+        let mut verify_copy;
 
         if self.decompress {
             self.method = match self.get_method(&mut stdin)? {
                 Some(method) => method,
                 None => {
+                    if self.json_records() {
+                        self.json_record(-1, None, None, None);
+                        self.write_json_totals();
+                    }
                     self.do_exit(self.exit_code);
                 }
             };
         }
 
         if self.list {
-            self.do_list(Some(&mut stdin), self.method)?;
+            self.do_list(None, self.method)?;
             return Ok(());
         }
 
         loop {
             if let Some(work_fn) = self.work {
                 self.ifd = Some(Box::new(io::stdin()));
-                self.ofd = Some(Box::new(io::stdout()));
+                self.ofd = if self.test {
+                    Some(Box::new(io::sink()))
+                } else {
+                    Some(Box::new(io::stdout()))
+                };
+                verify_copy = self.tee_for_verify();
                 if work_fn(self).is_err() {
+                    self.report_progress(true);
+                    if self.json_records() {
+                        let compressed = if self.decompress { self.bytes_in } else { self.bytes_out };
+                        self.json_record(-1, None, Some(compressed), None);
+                    }
                     return Ok(());
                 }
             } else {
@@ -880,12 +1431,26 @@ impl GzipState {
 
             self.method = match self.get_method(&mut stdin)? {
                 Some(method) => method,
-                None => return Ok(()),
+                None => {
+                    self.report_progress(true);
+                    return Ok(());
+                }
             };
             self.bytes_out = 0;
         }
 
-        if self.verbose != 0 {
+        self.report_progress(true);
+
+        if !self.verify_compressed(verify_copy)? {
+            if self.json_records() {
+                self.json_file_done();
+            }
+            return Ok(());
+        }
+
+        if self.json_records() {
+            self.json_file_done();
+        } else if self.verbose != 0 {
             if self.test {
                 eprintln!(" OK");
             } else if !self.decompress {
@@ -907,6 +1472,24 @@ impl GzipState {
         let imagic1: Option<u8>;
         let mut stamp: u32;
 
+        match self.container {
+            Container::Gzip => {}
+            Container::Zlib => return self.get_zlib_header(input),
+            Container::Raw => {
+                // Nothing to read: the input starts with the first block
+                self.method = DEFLATED;
+                self.part_nb += 1;
+                self.header_bytes = 0;
+                self.last_member = true;
+                self.preset_dict = self.dictionary.is_some();
+                self.work = Some(unzip);
+                return Ok(Some(self.method));
+            }
+        }
+
+        // Where this member, or the trailing data, starts in the input
+        let start = self.input_pos();
+
         if self.force == 0 && self.to_stdout {
             imagic0 = self.try_byte(input)?;
             if let Some(byte) = imagic0 {
@@ -932,16 +1515,16 @@ impl GzipState {
         self.method = -1;
         self.part_nb += 1;
         self.header_bytes = 0;
-        self.last_member = true;
+        self.last_member = false;
 
         if magic[0..2] == GZIP_MAGIC[..] || magic[0..2] == OLD_GZIP_MAGIC[..] {
             self.method = self.get_byte(input)? as i32;
             if self.method != DEFLATED {
                 eprintln!(
                     "{}: {}: unknown method {} -- not supported",
-                    self.program_name, self.ifname, self.method
+                    self.program_name, self.ifname.display(), self.method
                 );
-                self.exit_code = ERROR;
+                self.record_error(&format!("unknown method {} -- not supported", self.method));
                 return Ok(None);
             }
             self.work = Some(unzip);
@@ -950,17 +1533,17 @@ impl GzipState {
             if flags & ENCRYPTED != 0 {
                 eprintln!(
                     "{}: {} is encrypted -- not supported",
-                    self.program_name, self.ifname
+                    self.program_name, self.ifname.display()
                 );
-                self.exit_code = ERROR;
+                self.record_error("encrypted -- not supported");
                 return Ok(None);
             }
             if flags & RESERVED != 0 {
                 eprintln!(
                     "{}: {} has flags 0x{:x} -- not supported",
-                    self.program_name, self.ifname, flags
+                    self.program_name, self.ifname.display(), flags
                 );
-                self.exit_code = ERROR;
+                self.record_error(&format!("flags 0x{:x} -- not supported", flags));
                 if self.force <= 1 {
                     return Ok(None);
                 }
@@ -996,7 +1579,7 @@ impl GzipState {
                 if self.verbose != 0 {
                     eprintln!(
                         "{}: {}: extra field of {} bytes ignored",
-                        self.program_name, self.ifname, len
+                        self.program_name, self.ifname.display(), len
                     );
                 }
                 if flags & HEADER_CRC != 0 {
@@ -1009,27 +1592,35 @@ impl GzipState {
                 if self.no_name.unwrap_or(false) || (self.to_stdout && !self.list) || self.part_nb > 1 {
                     self.discard_input_bytes(input, usize::MAX, flags)?;
                 } else {
-                    let p = self.ofname.clone();
-                    let base = self.gzip_base_name(&p);
-                    let mut p_bytes = base.as_bytes().to_vec();
+                    // The stored name replaces the base name of ofname
+                    let mut p_bytes = Vec::new();
                     loop {
                         let byte = self.get_byte(input)?;
                         p_bytes.push(byte);
                         if byte == 0 {
                             break;
                         }
-                        if p_bytes.len() >= self.ofname.capacity() {
+                        if p_bytes.len() >= MAX_PATH_LEN {
                             self.gzip_error("corrupted input -- file name too large");
                         }
                     }
                     if flags & HEADER_CRC != 0 {
                         self.updcrc(Some(&p_bytes), p_bytes.len());
                     }
-                    let p_str = String::from_utf8_lossy(&p_bytes);
-                    let new_base = self.gzip_base_name(&p_str);
-                    self.ofname = new_base.to_string();
-                    if !self.list {
-                        self.make_legal_name();
+                    // The name is restored byte for byte, whatever its encoding.
+                    // A name without a usable base keeps the one derived from
+                    // ifname.
+                    let stored = Path::new(OsStr::from_bytes(&p_bytes[..p_bytes.len() - 1]));
+                    let base = self.gzip_base_name(stored);
+                    if !base.is_empty() && base != "." && base != ".." {
+                        let dir = self.ofname.parent().map(Path::to_path_buf);
+                        self.ofname = PathBuf::from(base);
+                        if !self.list {
+                            self.make_legal_name();
+                        }
+                        if let Some(dir) = dir.filter(|dir| !dir.as_os_str().is_empty()) {
+                            self.ofname = dir.join(&self.ofname);
+                        }
                     }
                 }
             }
@@ -1042,16 +1633,10 @@ impl GzipState {
                 let crc16 = self.updcrc(None, 0) & 0xffffffff;
                 let mut header16 = self.get_byte(input)? as u32;
                 header16 |= (self.get_byte(input)? as u32) << 8;
-                eprintln!(
-                        "{}: {}: header checksum 0x{:04x} != computed checksum 0x{:04x}",
-                        self.program_name, self.ifname, header16, crc16
-                    );
                 if header16 != crc16 {
-                    eprintln!(
-                        "{}: {}: header checksum 0x{:04x} != computed checksum 0x{:04x}",
-                        self.program_name, self.ifname, header16, crc16
-                    );
-                    self.exit_code = ERROR;
+                    let msg = format!("header checksum 0x{:04x} != computed checksum 0x{:04x}", header16, crc16);
+                    eprintln!("{}: {}: {}", self.program_name, self.ifname.display(), msg);
+                    self.record_error(&msg);
                     if self.force <= 1 {
                         return Ok(None);
                     }
@@ -1099,10 +1684,34 @@ impl GzipState {
         }
 
         if self.part_nb == 1 {
-            eprintln!("\n{}: {}: unexpected end of file", self.program_name, self.ifname);
-            self.exit_code = ERROR;
+            self.file_error("unexpected end of file");
             return Ok(None);
         } else {
+            // Not a failure: the members before were fine
+            self.method = -2;
+            self.trailing_offset = Some(start);
+            match self.trailing.clone() {
+                Trailing::Warn => {}
+                Trailing::Ignore => return Ok(None),
+                Trailing::Error => {
+                    self.method = -1;
+                    self.file_error(&format!("trailing data at byte {}", start));
+                    return Ok(None);
+                }
+                Trailing::Dump(path) => {
+                    let magic_len = if imagic1.is_some() { 2 } else { 1 };
+                    if let Err(err) = self.dump_trailing(input, &magic[..magic_len], &path) {
+                        eprintln!("{}: {}: {}", self.program_name, path.display(), err);
+                        self.exit_code = ERROR;
+                    } else if self.verbose != 0 {
+                        eprintln!(
+                            "\n{}: {}: decompression OK, trailing data written to {}",
+                            self.program_name, self.ifname.display(), path.display()
+                        );
+                    }
+                    return Ok(None);
+                }
+            }
             if magic[0] == 0 {
                 let mut inbyte = imagic1;
                 while inbyte == Some(0) {
@@ -1112,23 +1721,102 @@ impl GzipState {
                     if self.verbose != 0 {
                         eprintln!(
                             "\n{}: {}: decompression OK, trailing zero bytes ignored",
-                            self.program_name, self.ifname
+                            self.program_name, self.ifname.display()
                         );
+                        if self.exit_code == OK {
+                            self.exit_code = WARNING;
+                        }
                     }
                     return Ok(None);
                 }
             }
+            if !self.quiet {
+                eprintln!(
+                    "\n{}: {}: decompression OK, trailing garbage ignored",
+                    self.program_name, self.ifname.display()
+                );
+            }
+            if self.exit_code == OK {
+                self.exit_code = WARNING;
+            }
+            return Ok(None);
+        }
+    }
+
+    // --trailing-dump: write the data after the last member, from the magic
+    // bytes already read by get_method to the end of the input, to path
+    fn dump_trailing<R: Read>(&mut self, input: &mut R, magic: &[u8], path: &Path) -> io::Result<()> {
+        let mut out = File::create(path)?;
+        out.write_all(magic)?;
+        out.write_all(&self.inbuf[self.inptr..self.insize])?;
+        out.write_all(&self.pending_input)?;
+        self.bytes_in += self.pending_input.len() as i64;
+        self.pending_input.clear();
+        self.bytes_in += io::copy(input, &mut out)? as i64;
+        self.inptr = self.insize;
+        Ok(())
+    }
+
+    // Read and check the zlib header: CMF, FLG and the optional dictionary id.
+    fn get_zlib_header<R: Read>(&mut self, input: &mut R) -> io::Result<Option<i32>> {
+        self.method = -1;
+        self.part_nb += 1;
+        self.header_bytes = 0;
+        self.last_member = true;
+
+        let cmf = match self.try_byte(input)? {
+            Some(byte) => byte,
+            None => {
+                self.file_error("unexpected end of file");
+                return Ok(None);
+            }
+        };
+        let flg = self.get_byte(input)?;
+        if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+            eprintln!("{}: {}: not in zlib format", self.program_name, self.ifname.display());
+            self.record_error("not in zlib format");
+            return Ok(None);
+        }
+        if i32::from(cmf & 0x0f) != DEFLATED || cmf >> 4 > 7 {
             eprintln!(
-                "\n{}: {}: decompression OK, trailing garbage ignored",
-                self.program_name, self.ifname
+                "{}: {}: unknown method {} -- not supported",
+                self.program_name, self.ifname.display(), cmf & 0x0f
             );
+            self.record_error(&format!("unknown method {} -- not supported", cmf & 0x0f));
             return Ok(None);
         }
+
+        self.preset_dict = flg & FDICT != 0;
+        if self.preset_dict {
+            let mut id = [0u8; 4];
+            for byte in id.iter_mut() {
+                *byte = self.get_byte(input)?;
+            }
+            let id = u32::from_be_bytes(id);
+            let message = match &self.dictionary {
+                None => "needs a preset dictionary",
+                Some(dict) if adler32(1, dict) != id => "wrong preset dictionary",
+                Some(_) => "",
+            };
+            if !message.is_empty() {
+                eprintln!("{}: {}: {} (id 0x{:08x})", self.program_name, self.ifname.display(), message, id);
+                self.record_error(&format!("{} (id 0x{:08x})", message, id));
+                return Ok(None);
+            }
+        }
+
+        self.method = DEFLATED;
+        self.work = Some(unzip);
+        self.header_bytes = self.inptr + 4;
+        Ok(Some(self.method))
     }
 
     fn get_byte<R: Read>(&mut self, input: &mut R) -> io::Result<u8> {
         if self.inptr >= self.insize {
-            self.insize = input.read(&mut self.inbuf)?;
+            self.insize = match self.take_pending(0) {
+                0 => input.read(&mut self.inbuf)?,
+                n => n,
+            };
             self.bytes_in += self.insize as i64;
             self.inptr = 0;
             if self.insize == 0 {
@@ -1142,7 +1830,10 @@ impl GzipState {
 
     fn try_byte<R: Read>(&mut self, input: &mut R) -> io::Result<Option<u8>> {
         if self.inptr >= self.insize {
-            self.insize = input.read(&mut self.inbuf)?;
+            self.insize = match self.take_pending(0) {
+                0 => input.read(&mut self.inbuf)?,
+                n => n,
+            };
             self.bytes_in += self.insize as i64;
             self.inptr = 0;
             if self.insize == 0 {
@@ -1154,6 +1845,37 @@ impl GzipState {
         Ok(Some(byte))
     }
 
+    // Offset in the input of the next byte to be consumed
+    fn input_pos(&self) -> u64 {
+        (self.bytes_in - (self.insize - self.inptr) as i64) as u64
+    }
+
+    // Move bytes read ahead by --recover to inbuf[start..INBUFSIZ]. Returns
+    // how many were moved.
+    fn take_pending(&mut self, start: usize) -> usize {
+        let n = self.pending_input.len().min(INBUFSIZ - start);
+        self.inbuf[start..start + n].copy_from_slice(&self.pending_input[..n]);
+        self.pending_input.drain(..n);
+        n
+    }
+
+    // --recover: report the input bytes [start, end) that were skipped
+    fn report_damage(&mut self, start: u64, end: u64) {
+        if !self.quiet {
+            eprintln!(
+                "{}: {}: damaged data at bytes {}-{} skipped",
+                self.program_name,
+                self.ifname.display(),
+                start,
+                end
+            );
+        }
+        self.damaged.push((start, end));
+        if self.exit_code == OK {
+            self.exit_code = WARNING;
+        }
+    }
+
     fn discard_input_bytes<R: Read>(&mut self, input: &mut R, mut nbytes: usize, flags: u8) -> io::Result<()> {
         if nbytes != usize::MAX {
             while nbytes != 0 {
@@ -1191,16 +1913,8 @@ impl GzipState {
             // println!("is_none");
             self.crc16_digest = 0xffffffff;
         } else {
-            self.crc16_digest = self.crc16_digest; // 使用当前的 crc16_digest
-            // println!("crc={:?} len={:?}",self.crc16_digest, len);
             let bytes = buf.unwrap();
-            // println!("bytes={:?}", &bytes[..10]);
-            let mut i = 0;
-            while i < len {
-                self.crc16_digest = CRC_32_TAB[((self.crc16_digest ^ bytes[i] as u32) & 0xff) as usize] ^ (self.crc16_digest >> 8);
-                // println!("crc={:?}",self.crc16_digest);
-                i += 1;
-            }
+            self.crc16_digest = crc_update(self.crc16_digest, &bytes[..len]);
         }
         let count = COUNT.fetch_add(1, Ordering::SeqCst);
         // println!("crc={:?}",self.crc16_digest);
@@ -1208,16 +1922,26 @@ impl GzipState {
     }
     
 
-    fn gzip_base_name<'a>(&self, fname: &'a str) -> &'a str {
-        Path::new(fname)
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or(fname)
+    fn gzip_base_name<'a>(&self, fname: &'a Path) -> &'a OsStr {
+        fname.file_name().unwrap_or(fname.as_os_str())
+    }
+
+    // Report invalid data in the current file. The file fails, but the
+    // remaining ones are still processed.
+    fn file_error(&mut self, msg: &str) {
+        eprintln!("\n{}: {}: {}", self.program_name, self.ifname.display(), msg);
+        self.record_error(msg);
+    }
+
+    // Fail the current file, keeping msg for its --format=json record.
+    fn record_error(&mut self, msg: &str) {
+        self.exit_code = ERROR;
+        self.file_error = Some(msg.to_string());
     }
 
     fn gzip_error(&self, msg: &str) -> ! {
-        if !self.ifname.is_empty() {
-            eprintln!("{}: {}: {}", self.program_name, self.ifname, msg);
+        if !self.ifname.as_os_str().is_empty() {
+            eprintln!("{}: {}: {}", self.program_name, self.ifname.display(), msg);
         } else {
             eprintln!("{}: {}", self.program_name, msg);
         }
@@ -1225,24 +1949,13 @@ impl GzipState {
     }
 
     fn make_legal_name(&mut self) {
-        use std::path::Path;
-
         // Extract the file name without any directory components
-        if let Some(file_name) = Path::new(&self.ofname).file_name() {
-            self.ofname = file_name.to_string_lossy().into_owned();
+        if let Some(file_name) = self.ofname.file_name() {
+            self.ofname = PathBuf::from(file_name);
         }
 
-        // Replace any invalid characters in the file name
-        let invalid_chars = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
-        let mut legal_name = String::new();
-        for c in self.ofname.chars() {
-            if invalid_chars.contains(&c) {
-                legal_name.push('_');
-            } else {
-                legal_name.push(c);
-            }
-        }
-        self.ofname = legal_name;
+        // Any other byte is legal in a Unix file name, so the name is kept
+        // verbatim, even when it is not valid UTF-8
     }
 
     fn write_buf<W: Write>(&mut self, output: &mut W, buf: &[u8], count: usize) -> io::Result<()> {
@@ -1255,7 +1968,7 @@ impl GzipState {
         // Read the local file header
         let signature = self.read_u32_le(input)?;
         if signature != ZIP_LOCAL_HEADER_SIGNATURE {
-            eprintln!("{}: {}: not a valid zip file", self.program_name, self.ifname);
+            eprintln!("{}: {}: not a valid zip file", self.program_name, self.ifname.display());
             self.exit_code = ERROR;
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid ZIP file"));
         }
@@ -1275,11 +1988,11 @@ impl GzipState {
         // Read the file name
         let mut file_name_bytes = vec![0u8; file_name_length];
         input.read_exact(&mut file_name_bytes)?;
-        let file_name = String::from_utf8_lossy(&file_name_bytes);
+        let file_name = OsStr::from_bytes(&file_name_bytes);
 
         // Set the output file name if necessary
         if !self.no_name.unwrap_or(false) {
-            self.ofname = file_name.to_string();
+            self.ofname = PathBuf::from(file_name);
             if !self.list {
                 self.make_legal_name();
             }
@@ -1301,7 +2014,7 @@ impl GzipState {
             _ => {
                 eprintln!(
                     "{}: {}: unsupported compression method {} in zip file",
-                    self.program_name, self.ifname, self.method
+                    self.program_name, self.ifname.display(), self.method
                 );
                 self.exit_code = ERROR;
                 return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported compression method"));
@@ -1319,7 +2032,8 @@ impl GzipState {
         input.read_u32::<LittleEndian>()
     }
 
-    fn do_list<R: Read>(&mut self, input: Option<&mut R>, method: i32) -> io::Result<()> {
+    // List one file (input is None for stdin), or the totals if method < 0.
+    fn do_list(&mut self, mut input: Option<&mut File>, method: i32) -> io::Result<()> {
         const METHODS: [&str; MAX_METHODS] = [
             "store",  /* 0 */
             "compr",  /* 1 */
@@ -1337,7 +2051,12 @@ impl GzipState {
             o /= 10;
         }
 
-        if self.first_time && method >= 0 {
+        if self.format == Format::Json {
+            // Totals are written by run()
+            if method < 0 {
+                return Ok(());
+            }
+        } else if self.first_time && method >= 0 {
             self.first_time = false;
             if self.verbose != 0 {
                 print!("method  crc     date  time  ");
@@ -1373,22 +2092,41 @@ impl GzipState {
 
         let mut crc: u32 = !0; // unknown
         self.bytes_out = -1;
-        self.bytes_in = self.ifile_size.try_into().unwrap();
-
-        if !self.record_io && method == DEFLATED && !self.last_member {
-            // Get the crc and uncompressed size for gzip'ed (not zip'ed) files.
-            // If the seek fails, we could use read() to get to the end, but
-            // --list is used to get quick results.
-            // Use "gunzip < foo.gz | wc -c" to get the uncompressed size if
-            // you are not concerned about speed.
-            //self.bytes_in = input.seek(SeekFrom::End(-8))? as i64;
-            //if self.bytes_in != -1 {
-                let mut buf = [0u8; 8];
-                input.unwrap().read_exact(&mut buf)?;
-                self.bytes_in += 8;
-                crc = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
-                self.bytes_out = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as i64;
-            //}
+        let file_size = input.is_some().then_some(self.ifile_size);
+
+        if !self.record_io && method == DEFLATED {
+            // For a regular gzip file, the crc and uncompressed size are in the
+            // trailer. Anything else has to be decoded to get them.
+            let trailer = match input {
+                Some(ref mut file) if self.container == Container::Gzip && self.ifile_size >= 0 => {
+                    self.read_trailer(file)?
+                }
+                _ => None,
+            };
+            let info = match (trailer, input) {
+                (Some(info), _) => Some(info),
+                (None, Some(file)) => {
+                    let reader = Box::new(file.try_clone()?);
+                    self.decode_for_list(file, reader)?
+                }
+                // bytes_in keeps counting what is read from stdin
+                (None, None) => self.decode_for_list(&mut io::stdin(), Box::new(io::stdin()))?,
+            };
+            if let Some((info_crc, info_len)) = info {
+                crc = info_crc;
+                self.bytes_out = info_len;
+            }
+        }
+        // Decoding counts what it reads, which only matters for stdin
+        if let Some(size) = file_size {
+            self.bytes_in = size;
+        }
+
+        if self.format == Format::Json {
+            let known = |size: i64| Some(size).filter(|&size| size >= 0);
+            let crc = Some(crc).filter(|_| self.bytes_out >= 0);
+            self.json_record(method, crc, known(self.bytes_in), known(self.bytes_out));
+            return Ok(());
         }
 
         if self.verbose != 0 {
@@ -1434,11 +2172,148 @@ impl GzipState {
             self.bytes_out - (self.bytes_in as i64 - self.header_bytes as i64),
             self.bytes_out,
         );
-        println!(" {}", self.ofname);
+        // The name as stored, whatever its encoding
+        let mut out = stdout();
+        out.write_all(b" ")?;
+        out.write_all(self.ofname.as_os_str().as_bytes())?;
+        out.write_all(b"\n")?;
 
         Ok(())
     }
 
+    // Read the crc and uncompressed size from the trailer at the end of the
+    // file. They describe the last member only, so None is returned when the
+    // rest of the file is too large for a single member of that size, and
+    // when --verbose or --format=json ask for values checked by decoding.
+    fn read_trailer(&mut self, file: &mut File) -> io::Result<Option<(u32, i64)>> {
+        if self.ifile_size < self.header_bytes as i64 + 8 || self.verbose != 0 || self.format == Format::Json {
+            return Ok(None);
+        }
+        let pos = file.stream_position()?;
+        let mut buf = [0u8; 8];
+        file.seek(SeekFrom::End(-8))?;
+        file.read_exact(&mut buf)?;
+        file.seek(SeekFrom::Start(pos))?;
+
+        let crc = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let isize = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as u64;
+        // Worst case deflate output: fixed codes for every byte (9 bits
+        // each) or stored blocks with their 5-byte headers.
+        let bound = isize + (isize >> 3) + 5 * (isize / 16383 + 1) + 16;
+        if (self.ifile_size - self.header_bytes as i64) as u64 > bound {
+            return Ok(None);
+        }
+        Ok(Some((crc, isize as i64)))
+    }
+
+    // --verify: compressed data going to stdout cannot be read back, so
+    // decode it as it is written, on a thread fed by a TeeWriter
+    fn tee_for_verify(&mut self) -> Option<VerifyThread> {
+        if !self.verify || self.decompress || !self.to_stdout {
+            return None;
+        }
+        let out = self.ofd.take()?;
+        // A few chunks in flight at most: compression waits for the decoder
+        let (sender, receiver) = mpsc::sync_channel(4);
+        self.ofd = Some(Box::new(TeeWriter { out, sender: Some(sender) }));
+        let (container, dictionary, name) = (self.container, self.dictionary.clone(), self.ofname.clone());
+        Some(thread::spawn(move || {
+            let input = SharedReader(Rc::new(RefCell::new(ChannelInput { receiver, chunk: io::Cursor::default() })));
+            Self::decode_output(container, dictionary, name, input.clone(), Box::new(input))
+        }))
+    }
+
+    // --verify: decode the output of zip again, with the thread started by
+    // tee_for_verify or else from the output file, and check that it gives
+    // back the crc and length of the input. A mismatch fails the file.
+    fn verify_compressed(&mut self, decoder: Option<VerifyThread>) -> io::Result<bool> {
+        if !self.verify || self.decompress || self.method == -1 {
+            return Ok(true);
+        }
+        let decoded = match decoder {
+            Some(decoder) => {
+                // Dropping the TeeWriter ends the decoder input
+                if let Some(mut ofd) = self.ofd.take() {
+                    ofd.flush()?;
+                }
+                decoder.join().unwrap_or(Ok(None))?
+            }
+            None => {
+                let file = File::open(&self.tmp_ofname)?;
+                let reader = Box::new(file.try_clone()?);
+                Self::decode_output(self.container, self.dictionary.clone(), self.ofname.clone(), file, reader)?
+            }
+        };
+
+        let msg = match decoded {
+            None => "verification failed: the output does not decode",
+            Some((crc, len)) if crc != self.crc || len != self.bytes_in => {
+                "verification failed: the output does not decode to the input"
+            }
+            Some(_) => return Ok(true),
+        };
+        self.file_error(msg);
+        Ok(false)
+    }
+
+    // Decode compressed output for --verify, from input and reader which
+    // share their position. Gives the crc and length of the data, or None
+    // if it does not decode.
+    fn decode_output<R: Read>(
+        container: Container,
+        dictionary: Option<Vec<u8>>,
+        name: PathBuf,
+        mut input: R,
+        reader: Box<dyn Read>,
+    ) -> io::Result<Option<(u32, i64)>> {
+        let mut verifier = GzipState::new();
+        verifier.decompress = true;
+        verifier.quiet = true;
+        verifier.container = container;
+        verifier.dictionary = dictionary;
+        verifier.ifname = name;
+        verifier.clear_bufs();
+        match verifier.get_method(&mut input)? {
+            Some(method) => {
+                verifier.method = method;
+                verifier.decode_for_list(&mut input, reader)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Decode every member of the input for --list, returning the crc and
+    // length of all the uncompressed data, or None if it is damaged.
+    fn decode_for_list<R: Read>(&mut self, input: &mut R, reader: Box<dyn Read>) -> io::Result<Option<(u32, i64)>> {
+        let totals = Rc::new(Cell::new((0xffffffff, 0)));
+        self.ifd = Some(reader);
+        self.ofd = Some(Box::new(ListSink(totals.clone())));
+        let mut damaged = false;
+        loop {
+            self.bytes_out = 0;
+            let ok = match self.work {
+                Some(work_fn) => work_fn(self).is_ok(),
+                None => false,
+            };
+            if !ok {
+                damaged = true;
+                break;
+            }
+            if self.input_eof()? {
+                break;
+            }
+            match self.get_method(input)? {
+                Some(method) => self.method = method,
+                None => break,
+            }
+        }
+        self.ifd = None;
+        self.ofd = None;
+
+        let (crc, len) = totals.get();
+        Ok(if damaged { None } else { Some((crc ^ 0xffffffff, len)) })
+    }
+
     fn fprint_off<W: Write>(&self, file: &mut W, mut offset: i64, width: usize) -> io::Result<()> {
         // Buffer to hold the string representation of the offset
         let mut buf = [0u8; 65]; // 64 digits max for i64 plus sign
@@ -1449,7 +2324,7 @@ impl GzipState {
             // Build the digits in reverse order
             loop {
                 p -= 1;
-                buf[p] = b'0' - (offset % 10) as u8;
+                buf[p] = b'0' + (offset % 10).unsigned_abs() as u8;
                 offset /= 10;
                 if offset == 0 {
                     break;
@@ -1490,19 +2365,45 @@ impl GzipState {
         Ok(())
     }
 
-    // Function to install signal handlers
+    // Remove the partial output when interrupted. Signals ignored by whoever
+    // started us stay ignored, except SIGPIPE: that one is ignored by the Rust
+    // runtime itself, and gzip must die quietly when its reader goes away.
     fn install_signal_handlers(&self) {
-        // Implement signal handling if necessary
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = abort_gzip_signal as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            for &sig in HANDLED_SIGNALS.iter() {
+                libc::sigaddset(&mut action.sa_mask, sig);
+            }
+
+            for &sig in HANDLED_SIGNALS.iter() {
+                let mut old: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(sig, ptr::null(), &mut old);
+                if old.sa_sigaction != libc::SIG_IGN || sig == libc::SIGPIPE {
+                    libc::sigaction(sig, &action, ptr::null_mut());
+                }
+            }
+        }
+
+        // A panic must not leave a temporary output file behind either
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            remove_partial_output();
+            default_hook(info);
+        }));
     }
 
     fn make_ofname(&mut self) -> io::Result<()> {
-        if self.to_stdout {
+        if self.to_stdout && !self.list {
             // Output is stdout; no need to modify ofname
             return Ok(());
         }
 
         self.ofname = self.ifname.clone();
 
+        // Suffixes are matched and added on the raw bytes of the name, which
+        // need not be valid UTF-8
         if self.decompress {
             // Decompressing: remove the suffix
             if self.z_len == 0 {
@@ -1511,16 +2412,18 @@ impl GzipState {
                 return Err(io::Error::new(io::ErrorKind::Other, "no suffix specified"));
             }
 
-            if self.ofname.len() >= self.z_len && self.ofname.ends_with(&self.z_suffix) {
+            // As in GNU gzip, the suffix must leave a non-empty base name
+            let name = self.ifname.as_os_str().as_bytes();
+            let base_len = self.gzip_base_name(&self.ifname).len();
+            if base_len > self.z_len && name.ends_with(self.z_suffix.as_bytes()) {
                 // Remove the suffix
-                let new_len = self.ofname.len() - self.z_len;
-                self.ofname.truncate(new_len);
+                self.ofname = PathBuf::from(OsStr::from_bytes(&name[..name.len() - self.z_len]));
             } else {
                 // Input file does not have the expected suffix
                 if self.force == 0 && !self.list && !self.test {
                     eprintln!(
                         "{}: {}: unknown suffix -- ignored",
-                        self.program_name, self.ifname
+                        self.program_name, self.ifname.display()
                     );
                     self.exit_code = ERROR;
                     return Err(io::Error::new(io::ErrorKind::Other, "unknown suffix"));
@@ -1529,58 +2432,259 @@ impl GzipState {
                     self.ofname = self.ifname.clone();
                 }
             }
+        } else if let Some(suffix) = self.get_suffix().filter(|_| self.force == 0) {
+            // Don't compress an already compressed file. Stay quiet with -r
+            // so that a tree can be recompressed without noise. Like GNU
+            // gzip, this does not change the exit status.
+            if self.verbose > 0 || (!self.recursive && !self.quiet) {
+                eprintln!(
+                    "{}: {} already has {} suffix -- unchanged",
+                    self.program_name, self.ifname.display(), suffix
+                );
+            }
+            return Err(io::Error::new(io::ErrorKind::Other, "already compressed"));
         } else {
             // Compressing: append the suffix
-            self.ofname.push_str(&self.z_suffix);
+            let mut name = self.ifname.clone().into_os_string();
+            name.push(&self.z_suffix);
+            self.ofname = PathBuf::from(name);
         }
 
         Ok(())
     }
 
-    fn create_outfile(&self) -> io::Result<File> {
+    // Return the compressed suffix ifname ends with, if any. The suffix must
+    // not make up the whole base name.
+    fn get_suffix(&self) -> Option<String> {
+        let base = self.gzip_base_name(&self.ifname);
+        std::iter::once(self.z_suffix.as_str())
+            .chain(KNOWN_SUFFIXES)
+            .find(|suffix| base.len() > suffix.len() && base.as_bytes().ends_with(suffix.as_bytes()))
+            .map(str::to_string)
+    }
+
+    // Create a temporary file next to ofname to write the output into; it is
+    // renamed over ofname once complete. It is registered for removal on a
+    // signal from the moment it exists.
+    fn create_outfile(&mut self) -> io::Result<File> {
         use std::fs::OpenOptions;
-        let mut options = OpenOptions::new();
-        options.write(true);
 
-        if self.force == 1 {
-            options.create(true).truncate(true);
-        } else {
-            options.create_new(true);
+        if self.force != 1 && fs::symlink_metadata(&self.ofname).is_ok() {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST));
         }
 
-        let file = options.open(&self.ofname)?;
-        Ok(file)
+        let dir = self.ofname.parent().unwrap_or(Path::new(""));
+        let base = self.ofname.file_name().unwrap_or_default();
+        let mut attempt = 0;
+        loop {
+            let mut tmp_base = OsString::from(".");
+            tmp_base.push(base);
+            tmp_base.push(format!(".{}.{}", std::process::id(), attempt));
+            let tmp_ofname = dir.join(tmp_base);
+            // The name is registered before the file is created, with the
+            // signals held back so they can't remove a file that isn't ours
+            let old_mask = block_signals();
+            set_remove_ofname(Some(&tmp_ofname));
+            // Only the owner may read the output until copy_stat sets its mode
+            let created = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp_ofname);
+            if created.is_err() {
+                set_remove_ofname(None);
+            }
+            restore_signals(&old_mask);
+            match created {
+                Ok(file) => {
+                    self.tmp_ofname = tmp_ofname;
+                    return Ok(file);
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+                Err(err) => return Err(err),
+            }
+        }
     }
 
+    // Give the output the input's timestamps, extended attributes, ownership
+    // and permissions, as far as the file system and our privileges allow.
     fn copy_stat(&self) -> io::Result<()> {
-        // Copy file metadata from input to output
-        // For simplicity, we'll set the modified time
-        if let Some(ref time_stamp) = self.time_stamp {
-            let result = filetime::set_file_mtime(&self.ofname, filetime::FileTime::from_system_time(*time_stamp));
-            if let Err(err) = result {
-                eprintln!("{}: {}", self.program_name, err);
+        let istat = match self.istat {
+            Some(ref istat) => istat,
+            None => return Ok(()),
+        };
+
+        let atime = FileTime::from_last_access_time(istat);
+        let mtime = match self.time_stamp {
+            // The time stored in the gzip header wins when restoring with -N
+            Some(stamp) if self.decompress && !self.no_time.unwrap_or(false) => {
+                FileTime::from_system_time(stamp)
             }
+            _ => FileTime::from_last_modification_time(istat),
+        };
+        if let Err(err) = filetime::set_file_times(&self.tmp_ofname, atime, mtime) {
+            self.warn_stat(&err);
+        }
+
+        // Before the chmod, which may take away our own write permission
+        if let Err(err) = copy_xattrs(Path::new(&self.ifname), Path::new(&self.tmp_ofname)) {
+            self.warn_stat(&err);
+        }
+
+        // Like GNU gzip: group first, then mode, then owner. Changing the
+        // ownership only works when privileged, so failures are ignored.
+        let _ = std::os::unix::fs::chown(&self.tmp_ofname, None, Some(istat.gid()));
+        let mode = fs::Permissions::from_mode(istat.mode() & 0o7777);
+        if let Err(err) = fs::set_permissions(&self.tmp_ofname, mode) {
+            self.warn_stat(&err);
         }
+        let _ = std::os::unix::fs::chown(&self.tmp_ofname, Some(istat.uid()), None);
         Ok(())
     }
 
+    fn warn_stat(&self, err: &io::Error) {
+        if !self.quiet {
+            eprintln!("{}: {}: {}", self.program_name, self.ofname.display(), err);
+        }
+    }
+
+    // Flush the directory entry naming the output to disk.
+    fn sync_output_dir(&self) -> io::Result<()> {
+        let dir = match Path::new(&self.ofname).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()
+    }
+
     fn remove_output_file(&self) -> io::Result<()> {
-        fs::remove_file(&self.ofname)?;
+        set_remove_ofname(None);
+        fs::remove_file(&self.tmp_ofname)?;
         Ok(())
     }
 
-    fn input_eof(&self) -> io::Result<bool> {
-        // Implement logic to check if input EOF is reached
-        // For simplicity, return true to end the loop
-        Ok(true)
+    // Check whether the input is exhausted after a member: true when
+    // compressing, after a format that can't be concatenated, or at EOF.
+    fn input_eof(&mut self) -> io::Result<bool> {
+        if !self.decompress || self.last_member {
+            return Ok(true);
+        }
+        if self.inptr == self.insize {
+            if self.fill_inbuf(true)?.is_none() {
+                return Ok(true);
+            }
+            // Put back the byte fill_inbuf consumed
+            self.inptr = 0;
+        }
+        Ok(false)
     }
 
     fn display_ratio(&self, num: i64, den: i64) {
-        if den == 0 {
-            print!("inf%");
+        let ratio = if den == 0 { 0.0 } else { 100.0 * num as f64 / den as f64 };
+        print!("{:5.1}%", ratio);
+    }
+
+    // Whether results are reported as --format=json records
+    fn json_records(&self) -> bool {
+        self.format == Format::Json && (self.list || self.test || self.verbose != 0)
+    }
+
+    // Emit the --format=json record of the current file. The crc and sizes
+    // are None when unknown.
+    fn json_record(&mut self, method: i32, crc: Option<u32>, compressed: Option<i64>, uncompressed: Option<i64>) {
+        let method_name = match method {
+            0 => Some("store"),
+            1 => Some("compress"),
+            2 => Some("pack"),
+            3 => Some("lzh"),
+            DEFLATED => Some("deflate"),
+            _ => None,
+        };
+        let ratio = match (compressed, uncompressed) {
+            (Some(compressed), Some(uncompressed)) => {
+                let saved = uncompressed - (compressed - self.header_bytes as i64);
+                let ratio = if uncompressed == 0 { 0.0 } else { 100.0 * saved as f64 / uncompressed as f64 };
+                // Same precision as the text output
+                Some((ratio * 10.0).round() / 10.0)
+            }
+            _ => None,
+        };
+        let mtime = self
+            .time_stamp
+            .and_then(|stamp| stamp.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs());
+        let output = if self.test { None } else { Some(self.ofname.to_string_lossy().into_owned()) };
+        let error = self.file_error.take();
+        let test = if !self.test {
+            None
+        } else if error.is_some() {
+            Some("failed")
+        } else if !self.damaged.is_empty() {
+            Some("damaged")
+        } else {
+            Some("ok")
+        };
+        // Input ranges skipped by --recover, as [start, end) byte offsets
+        let damaged = Some(&self.damaged).filter(|ranges| !ranges.is_empty());
+
+        self.json_totals.files += 1;
+        self.json_totals.errors += error.is_some() as u64;
+        self.json_totals.damaged += (error.is_none() && damaged.is_some()) as u64;
+        self.json_totals.compressed += compressed.unwrap_or(0);
+        self.json_totals.uncompressed += uncompressed.unwrap_or(0);
+
+        self.write_json(&serde_json::json!({
+            "name": self.ifname.to_string_lossy(),
+            "output": output,
+            "method": method_name,
+            "crc": crc,
+            "mtime": mtime,
+            "compressed": compressed,
+            "uncompressed": uncompressed,
+            "ratio": ratio,
+            "trailing": self.trailing_offset(),
+            "test": test,
+            "error": error,
+            "damaged": damaged,
+        }));
+    }
+
+    // Emit the --format=json record of a file that went through
+    fn json_file_done(&mut self) {
+        let (compressed, uncompressed, crc) = if self.decompress {
+            // One record per file: bytes_out and the crc register start over
+            // with each member, so report all of the output, as gzip -l does
+            (self.bytes_in, self.file_out as i64, self.file_crc ^ 0xffffffff)
+        } else {
+            (self.bytes_out, self.bytes_in, self.crc)
+        };
+        // get_method leaves a negative method after the last gzip member
+        let method = if self.decompress && self.method >= 0 { self.method } else { DEFLATED };
+        self.json_record(method, Some(crc), Some(compressed), Some(uncompressed));
+    }
+
+    // Emit the final --format=json object with the sums over all records
+    fn write_json_totals(&self) {
+        let totals = &self.json_totals;
+        let ratio = if totals.uncompressed == 0 {
+            0.0
+        } else {
+            100.0 * (totals.uncompressed - totals.compressed) as f64 / totals.uncompressed as f64
+        };
+        self.write_json(&serde_json::json!({
+            "totals": {
+                "files": totals.files,
+                "errors": totals.errors,
+                "damaged": totals.damaged,
+                "compressed": totals.compressed,
+                "uncompressed": totals.uncompressed,
+                "ratio": (ratio * 10.0).round() / 10.0,
+            }
+        }));
+    }
+
+    // Records go to stdout, unless that is where the data goes
+    fn write_json(&self, value: &serde_json::Value) {
+        if self.to_stdout && !self.list && !self.test {
+            eprintln!("{}", value);
         } else {
-            let ratio = 100.0 * num as f64 / den as f64;
-            print!("{:.2}%", ratio);
+            println!("{}", value);
         }
     }
 
@@ -1591,13 +2695,98 @@ impl GzipState {
         self.insize = 0;
         self.inptr = 0;
         self.outcnt = 0;
+        self.pending_input.clear();
+        self.file_out = 0;
+        self.file_crc = 0xffffffff;
+        self.trailing_offset = None;
+        if let Some(meter) = self.progress.as_mut() {
+            meter.start();
+        }
+    }
+
+    /// Install a hook receiving progress reports while files are processed.
+    pub fn set_progress_hook(&mut self, hook: ProgressHook) {
+        self.progress = Some(ProgressMeter::new(hook));
+    }
+
+    /// Fail decompression of any file that would produce more than limit
+    /// bytes. The output stops at the limit.
+    pub fn set_max_output(&mut self, limit: Option<u64>) {
+        self.max_output = limit;
+    }
+
+    /// Fail decompression of any file that would produce more than limit
+    /// bytes of output per byte of input read so far.
+    pub fn set_max_ratio(&mut self, limit: Option<u64>) {
+        self.max_ratio = limit;
+    }
+
+    /// Choose what happens to data after the last member of a file.
+    pub fn set_trailing(&mut self, trailing: Trailing) {
+        self.trailing = trailing;
+    }
+
+    /// Offset in the current input where data after the last member starts,
+    /// if there is any.
+    pub fn trailing_offset(&self) -> Option<u64> {
+        self.trailing_offset
+    }
+
+    // How many of the next count output bytes the --max-output and
+    // --max-ratio limits let through, with the error for the limit hit when
+    // that is not all of them.
+    fn output_room(&self, count: usize) -> (usize, Option<io::Error>) {
+        let mut room = u64::MAX;
+        let mut hit = None;
+        if let Some(max) = self.max_output {
+            room = max.saturating_sub(self.file_out);
+            hit = Some("output exceeds the --max-output limit");
+        }
+        if let Some(ratio) = self.max_ratio {
+            let ratio_room = (self.bytes_in.max(0) as u64)
+                .saturating_mul(ratio)
+                .saturating_sub(self.file_out);
+            if ratio_room < room {
+                room = ratio_room;
+                hit = Some("expansion ratio exceeds the --max-ratio limit");
+            }
+        }
+        if room >= count as u64 {
+            return (count, None);
+        }
+        let err = hit.map(|msg| io::Error::new(io::ErrorKind::FileTooLarge, msg));
+        (room as usize, err)
+    }
+
+    /// Pass the input consumed so far to the progress hook, if any. Reports
+    /// are rate-limited unless done is set.
+    pub(crate) fn report_progress(&mut self, done: bool) {
+        if let Some(meter) = self.progress.as_mut() {
+            let total = u64::try_from(self.ifile_size).ok();
+            meter.update(&self.ifname, self.bytes_in.max(0) as u64, total, done);
+        }
     }
 
     // Function to write a single byte
     fn put_byte(&mut self, byte: u8) -> io::Result<()> {
-        // 直接写入原始字节,不进行任何编码转换
-        self.ofd.as_mut().unwrap().write_all(&[byte])?;
+        self.outbuf[self.outcnt] = byte;
         self.outcnt += 1;
+        if self.outcnt == OUTBUFSIZ {
+            self.flush_outbuf()?;
+        }
+        Ok(())
+    }
+
+    /// Write the output buffer outbuf[0..outcnt-1] and update bytes_out.
+    /// (used for the compressed data only)
+    fn flush_outbuf(&mut self) -> io::Result<()> {
+        if self.outcnt == 0 {
+            return Ok(());
+        }
+
+        self.ofd.as_mut().unwrap().write_all(&self.outbuf[..self.outcnt])?;
+        self.bytes_out += self.outcnt as i64;
+        self.outcnt = 0;
         Ok(())
     }
 
@@ -1627,8 +2816,14 @@ impl GzipState {
     }
 
     fn put_short(&mut self, value: u16) {
-        self.put_byte((value & 0xFF) as u8).unwrap();        // Lower byte
-        self.put_byte(((value >> 8) & 0xFF) as u8).unwrap(); // Upper byte
+        if self.outcnt < OUTBUFSIZ - 2 {
+            self.outbuf[self.outcnt] = (value & 0xFF) as u8;          // Lower byte
+            self.outbuf[self.outcnt + 1] = ((value >> 8) & 0xFF) as u8; // Upper byte
+            self.outcnt += 2;
+        } else {
+            self.put_byte((value & 0xFF) as u8).unwrap();
+            self.put_byte(((value >> 8) & 0xFF) as u8).unwrap();
+        }
     }
 
     fn bi_windup(&mut self) {
@@ -1643,10 +2838,8 @@ impl GzipState {
 
     // Function to write a 4-byte little-endian unsigned long
     fn put_long(&mut self, value: u32) -> io::Result<()> {
-        let bytes = value.to_le_bytes();
-        self.ofd.as_mut().unwrap().write_all(&bytes)?;
-        self.outcnt += 4;
-//         self.crc16_digest = self.updcrc(Some(&bytes), bytes.len());
+        self.put_short((value & 0xffff) as u16);
+        self.put_short((value >> 16) as u16);
         Ok(())
     }
 
@@ -1664,6 +2857,12 @@ impl GzipState {
                 break;
             }
 
+            let pending = self.take_pending(self.insize);
+            if pending > 0 {
+                self.insize += pending;
+                continue;
+            }
+
             // Attempt to read into the buffer starting at `insize`
             match &mut self.ifd {
                 Some(ifd) => {
@@ -1692,6 +2891,7 @@ impl GzipState {
         }
 
         self.bytes_in += self.insize as i64;
+        self.report_progress(false);
         self.inptr = 1; // Set the read pointer to 1, assuming 0 is processed elsewhere
         Ok(Some(self.inbuf[0]))
     }
@@ -1703,7 +2903,22 @@ impl GzipState {
         }
         // println!("flush: outcnt={:?}",state.outcnt);
 
-        self.updcrc(Some(&self.window.clone()), self.outcnt);
+        // Nothing past an output limit is written
+        let (count, limit_err) = self.output_room(self.outcnt);
+        if let Some(err) = limit_err {
+            if !self.test {
+                self.ofd.as_mut().expect("REASON").write_all(&self.window[..count])?;
+            }
+            self.outcnt = 0;
+            return Err(err);
+        }
+        self.file_out += count as u64;
+
+        self.crc16_digest = crc_update(self.crc16_digest, &self.window[..self.outcnt]);
+        self.file_crc = crc_update(self.file_crc, &self.window[..self.outcnt]);
+        if self.container == Container::Zlib {
+            self.adler = adler32(self.adler, &self.window[..self.outcnt]);
+        }
 
         if !self.test {
             self.ofd.as_mut().expect("REASON").write_all(&self.window[0..self.outcnt])?;
@@ -1740,6 +2955,243 @@ fn copy(_state: &mut GzipState) -> io::Result<()> {
     unimplemented!()
 }
 
+/// Parse a byte count with an optional k, M, G or T suffix (powers of 1024).
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, shift) = match value.char_indices().last()? {
+        (i, 'k' | 'K') => (&value[..i], 10),
+        (i, 'm' | 'M') => (&value[..i], 20),
+        (i, 'g' | 'G') => (&value[..i], 30),
+        (i, 't' | 'T') => (&value[..i], 40),
+        _ => (value, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Decoder started by tee_for_verify, giving the crc and length of what the
+/// compressed data decodes to, or None if it does not decode.
+type VerifyThread = thread::JoinHandle<io::Result<Option<(u32, i64)>>>;
+
+/// Output for --verify when compressing to stdout: passes the data on and
+/// sends a copy of it to the decoder.
+struct TeeWriter {
+    out: Box<dyn Write>,
+    sender: Option<mpsc::SyncSender<Vec<u8>>>,
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        // A decoder that stopped early has its verdict already
+        if let Some(sender) = &self.sender {
+            if sender.send(buf[..n].to_vec()).is_err() {
+                self.sender = None;
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Input of the --verify decoder: the chunks sent by a TeeWriter, up to
+/// when it goes away.
+struct ChannelInput {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: io::Cursor<Vec<u8>>,
+}
+
+impl Read for ChannelInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.position() == self.chunk.get_ref().len() as u64 {
+            match self.receiver.recv() {
+                Ok(data) => self.chunk = io::Cursor::new(data),
+                Err(_) => return Ok(0),
+            }
+        }
+        self.chunk.read(buf)
+    }
+}
+
+/// Input whose clones share the read position, as clones of a File do.
+struct SharedReader<R>(Rc<RefCell<R>>);
+
+impl<R> Clone for SharedReader<R> {
+    fn clone(&self) -> Self {
+        SharedReader(self.0.clone())
+    }
+}
+
+impl<R: Read> Read for SharedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+/// Output for `gzip -l` when the data has to be decoded: discards it, keeping
+/// the CRC-32 register and the length.
+struct ListSink(Rc<Cell<(u32, i64)>>);
+
+impl Write for ListSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (crc, len) = self.0.get();
+        self.0.set((crc_update(crc, buf), len + buf.len() as i64));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run the table-driven CRC-32 over `bytes`, starting from the raw
+/// (pre-inverted) register value `crc`.
+fn crc_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc = CRC_32_TAB[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Register `name` as the output to remove if a signal arrives, or forget
+/// the current one.
+fn set_remove_ofname(name: Option<&Path>) {
+    let name = name
+        .and_then(|name| CString::new(name.as_os_str().as_bytes()).ok())
+        .map_or(ptr::null_mut(), CString::into_raw);
+    let old = REMOVE_OFNAME.swap(name, Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { CString::from_raw(old) });
+    }
+}
+
+/// Hold back HANDLED_SIGNALS, returning the signal mask to restore.
+fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        let mut old: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for &sig in &HANDLED_SIGNALS {
+            libc::sigaddset(&mut set, sig);
+        }
+        libc::sigprocmask(libc::SIG_BLOCK, &set, &mut old);
+        old
+    }
+}
+
+fn restore_signals(old: &libc::sigset_t) {
+    unsafe {
+        libc::sigprocmask(libc::SIG_SETMASK, old, ptr::null_mut());
+    }
+}
+
+/// Remove the output registered with set_remove_ofname, if any.
+fn remove_partial_output() {
+    let name = REMOVE_OFNAME.swap(ptr::null_mut(), Ordering::SeqCst);
+    if !name.is_null() {
+        let name = unsafe { CString::from_raw(name) };
+        let _ = fs::remove_file(OsStr::from_bytes(name.as_bytes()));
+    }
+}
+
+/// Handler for HANDLED_SIGNALS: remove the partial output, then die from the
+/// same signal so the parent sees the conventional status.
+extern "C" fn abort_gzip_signal(sig: libc::c_int) {
+    let name = REMOVE_OFNAME.swap(ptr::null_mut(), Ordering::SeqCst);
+    unsafe {
+        if !name.is_null() {
+            libc::unlink(name);
+        }
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+/// Copy every extended attribute of `src` to `dst`, which includes POSIX ACLs
+/// on Linux. Attributes the file system or our privileges don't allow are skipped.
+#[cfg(target_os = "linux")]
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let path_cstr = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+    };
+    let skippable = |err: &io::Error| {
+        matches!(
+            err.raw_os_error(),
+            Some(libc::ENOTSUP) | Some(libc::EPERM) | Some(libc::EACCES) | Some(libc::ENODATA)
+        )
+    };
+    let src = path_cstr(src)?;
+    let dst = path_cstr(dst)?;
+
+    let len = unsafe { libc::listxattr(src.as_ptr(), std::ptr::null_mut(), 0) };
+    if len <= 0 {
+        let err = io::Error::last_os_error();
+        return if len == 0 || skippable(&err) { Ok(()) } else { Err(err) };
+    }
+    let mut names = vec![0u8; len as usize];
+    let len = unsafe { libc::listxattr(src.as_ptr(), names.as_mut_ptr() as *mut libc::c_char, names.len()) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(len as usize);
+
+    for name in names.split_inclusive(|&b| b == 0) {
+        let name = CStr::from_bytes_with_nul(name).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        let size = unsafe { libc::getxattr(src.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            if skippable(&err) { continue; }
+            return Err(err);
+        }
+        let mut value = vec![0u8; size as usize];
+        let size = unsafe {
+            libc::getxattr(src.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut libc::c_void, value.len())
+        };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            if skippable(&err) { continue; }
+            return Err(err);
+        }
+        let ret = unsafe {
+            libc::setxattr(dst.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, size as usize, 0)
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if !skippable(&err) {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Update the Adler-32 checksum `adler` (1 for an empty stream) with `bytes`.
+fn adler32(adler: u32, bytes: &[u8]) -> u32 {
+    const BASE: u32 = 65521; // largest prime smaller than 65536
+    const NMAX: usize = 5552; // longest run before the sums can overflow
+    let mut a = adler & 0xffff;
+    let mut b = adler >> 16;
+    for chunk in bytes.chunks(NMAX) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= BASE;
+        b %= BASE;
+    }
+    (b << 16) | a
+}
+
 // CRC calculation functions
 fn crc32d(c: u32, data: u64) -> u32 {
     // Implement CRC32D logic (using the equivalent algorithm)
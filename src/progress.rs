@@ -0,0 +1,116 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two progress reports for the same file.
+const REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Snapshot of the work done on the current file, passed to the progress hook.
+pub struct Progress<'a> {
+    /// File being processed ("stdin" for standard input).
+    pub name: &'a Path,
+    /// Input bytes consumed so far.
+    pub bytes_in: u64,
+    /// Size of the input, when known.
+    pub total: Option<u64>,
+    /// Time since processing of the file started.
+    pub elapsed: Duration,
+    /// Set on the last report for the file.
+    pub done: bool,
+}
+
+impl Progress<'_> {
+    /// Percentage of the input consumed, when its size is known.
+    pub fn percent(&self) -> Option<f64> {
+        self.total
+            .filter(|&total| total > 0)
+            .map(|total| (100.0 * self.bytes_in as f64 / total as f64).min(100.0))
+    }
+
+    /// Average throughput in input bytes per second.
+    pub fn rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_in as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Estimated time left, when the input size is known.
+    pub fn eta(&self) -> Option<Duration> {
+        let rate = self.rate();
+        let total = self.total?;
+        if rate <= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(total.saturating_sub(self.bytes_in) as f64 / rate))
+    }
+}
+
+/// Callback receiving progress reports.
+pub type ProgressHook = Box<dyn FnMut(&Progress)>;
+
+/// Rate-limits the calls to a progress hook and keeps the start time of the
+/// current file.
+pub(crate) struct ProgressMeter {
+    hook: ProgressHook,
+    start: Instant,
+    last: Option<Instant>,
+}
+
+impl ProgressMeter {
+    pub(crate) fn new(hook: ProgressHook) -> Self {
+        ProgressMeter { hook, start: Instant::now(), last: None }
+    }
+
+    /// Restart the clock for a new file.
+    pub(crate) fn start(&mut self) {
+        self.start = Instant::now();
+        self.last = None;
+    }
+
+    pub(crate) fn update(&mut self, name: &Path, bytes_in: u64, total: Option<u64>, done: bool) {
+        let now = Instant::now();
+        if !done && self.last.is_some_and(|last| now - last < REPORT_INTERVAL) {
+            return;
+        }
+        self.last = Some(now);
+        (self.hook)(&Progress { name, bytes_in, total, elapsed: now - self.start, done });
+    }
+}
+
+/// Progress hook used by --progress: keeps a single status line up to date
+/// on stderr.
+pub fn print_progress(progress: &Progress) {
+    let mut line = format!("{}: {}", progress.name.display(), format_size(progress.bytes_in as f64));
+    if let Some(percent) = progress.percent() {
+        line.push_str(&format!(" {:5.1}%", percent));
+    }
+    line.push_str(&format!(" {}/s", format_size(progress.rate())));
+    if !progress.done {
+        if let Some(eta) = progress.eta() {
+            let secs = eta.as_secs();
+            line.push_str(&format!(" ETA {}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60));
+        }
+    }
+    // Return to the start of the line and clear what is left of the last one
+    eprint!("\r{}\x1b[K", line);
+    if progress.done {
+        eprintln!();
+    }
+}
+
+fn format_size(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
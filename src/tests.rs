@@ -352,6 +352,9 @@ fn test_compression_existing_output() {
         .output()
         .expect("Failed to execute system gzip");
 
+    // Cleanup, before a failed assertion can skip it
+    fs::remove_file(&gz_path).ok();
+
     // Compare outputs
     assert_eq!(
         result.status.code(),
@@ -363,9 +366,6 @@ fn test_compression_existing_output() {
         system_result.stderr,
         "Error messages don't match"
     );
-    
-    // Cleanup
-    fs::remove_file(&gz_path).ok();
     // fs::remove_file(test_path).ok();
 }
 
@@ -390,6 +390,9 @@ fn test_decompression_existing_output() {
         .output()
         .expect("Failed to execute system gzip");
 
+    // Cleanup, before a failed assertion can skip it
+    fs::remove_file(&gz_path).ok();
+
     // Compare outputs
     assert_eq!(
         result.status.code(),
@@ -401,9 +404,6 @@ fn test_decompression_existing_output() {
         system_result.stderr,
         "Error messages don't match"
     );
-    
-    // Cleanup
-    fs::remove_file(&gz_path).ok();
 }
 
 #[test]
@@ -448,10 +448,16 @@ fn test_combined_options() {
 
 #[test]
 fn test_suffix_option() {
+    // The .gzip output goes next to the input, so keep it out of the tree
+    let temp_dir = tempfile::tempdir().unwrap();
+    let input = temp_dir.path().join("test-word.txt");
+    fs::copy("tests/test-word.txt", &input).unwrap();
+    let input = input.to_str().unwrap();
     assert!(compare_gzip_outputs(
-        &["-k", "-f","-1", "-S", ".gzip", "tests/test-word.txt"],
-        Some("tests/test-word.txt")
+        &["-k", "-f","-1", "-S", ".gzip", input],
+        Some(input)
     ));
+    assert!(temp_dir.path().join("test-word.txt.gzip").exists());
 }
 
 #[test]
@@ -810,6 +816,28 @@ fn test_suffix_option_decompress() {
     assert!(compare_gzip_decompress_outputs(&["-d", "-S", ".gzip"], test_file));
 }
 
+#[test]
+fn test_decompress_suffix_only_name() {
+    // A file named just ".gz" has no name left once the suffix is removed
+    let temp_dir = tempfile::tempdir().unwrap();
+    let input = temp_dir.path().join(".gz");
+    let compressed = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c", "tests/test-word.txt"])
+        .output()
+        .expect("Failed to execute our gzip");
+    fs::write(&input, &compressed.stdout).unwrap();
+
+    let output = Command::new("./target/debug/gzip")
+        .arg("-d")
+        .arg(&input)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown suffix -- ignored"));
+    assert!(input.exists());
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+}
+
 #[test]
 fn test_gzip_env_valid_option() {
     // Create test file
@@ -1364,4 +1392,1295 @@ fn test_reproducible_decompression() {
             content2
         );
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_ultra_compression() {
+    let input = "tests/test-passage.txt";
+    let ultra_file = NamedTempFile::new().unwrap();
+    let best_file = NamedTempFile::new().unwrap();
+    let decompressed_file = NamedTempFile::new().unwrap();
+
+    // Compress with our ultra level
+    let status = Command::new("./target/debug/gzip")
+        .args(&["--ultra", "-c", "-n"])
+        .stdin(File::open(input).unwrap())
+        .stdout(Stdio::from(ultra_file.reopen().unwrap()))
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success(), "Ultra compression failed");
+
+    // Compress with system gzip -9 for reference
+    Command::new("gzip")
+        .args(&["-9", "-c", "-n"])
+        .stdin(File::open(input).unwrap())
+        .stdout(Stdio::from(best_file.reopen().unwrap()))
+        .status()
+        .expect("Failed to execute system gzip");
+
+    // The output must be a standard gzip stream
+    let status = Command::new("gzip")
+        .args(&["-d", "-c"])
+        .stdin(File::open(ultra_file.path()).unwrap())
+        .stdout(Stdio::from(decompressed_file.reopen().unwrap()))
+        .status()
+        .expect("Failed to execute system gzip");
+    assert!(status.success(), "System gzip rejected the ultra output");
+
+    let original = fs::read(input).unwrap();
+    let decompressed = fs::read(decompressed_file.path()).unwrap();
+    assert!(original == decompressed, "Ultra output does not decompress to the input");
+
+    let ultra_size = fs::metadata(ultra_file.path()).unwrap().len();
+    let best_size = fs::metadata(best_file.path()).unwrap().len();
+    assert!(
+        ultra_size <= best_size,
+        "Ultra output ({} bytes) is larger than gzip -9 ({} bytes)",
+        ultra_size,
+        best_size
+    );
+}
+
+#[test]
+fn test_strategies() {
+    let input = "tests/test-passage.txt";
+    let original = fs::read(input).unwrap();
+
+    for strategy in ["default", "filtered", "huffman", "rle", "fixed"] {
+        let compressed_file = NamedTempFile::new().unwrap();
+        let decompressed_file = NamedTempFile::new().unwrap();
+        let strategy_arg = format!("--strategy={}", strategy);
+
+        // Compress with our implementation
+        let status = Command::new("./target/debug/gzip")
+            .args(&["-1", "-c", strategy_arg.as_str()])
+            .stdin(File::open(input).unwrap())
+            .stdout(Stdio::from(compressed_file.reopen().unwrap()))
+            .status()
+            .expect("Failed to execute our gzip");
+        assert!(status.success(), "Compression failed for strategy {}", strategy);
+
+        // Decompress with system gzip
+        Command::new("gzip")
+            .args(&["-d", "-c"])
+            .stdin(File::open(compressed_file.path()).unwrap())
+            .stdout(Stdio::from(decompressed_file.reopen().unwrap()))
+            .status()
+            .expect("Failed to execute system gzip");
+
+        let decompressed = fs::read(decompressed_file.path()).unwrap();
+        assert!(original == decompressed, "Content mismatch for strategy {}", strategy);
+    }
+
+    // Unknown strategies are rejected
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-c", "--strategy=bogus", input])
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(1), "Exit status codes don't match");
+}
+
+#[test]
+fn test_preset_dictionary() {
+    let input = "tests/test-paragraphs.txt";
+    let original = fs::read(input).unwrap();
+
+    for container in ["--zlib", "--raw"] {
+        let compress = |extra: &[&str]| {
+            Command::new("./target/debug/gzip")
+                .args(&["-1", "-c", container])
+                .args(extra)
+                .stdin(File::open(input).unwrap())
+                .output()
+                .expect("Failed to execute our gzip")
+        };
+        let plain = compress(&[]);
+        let primed = compress(&["--dict", input]);
+        assert!(primed.status.success(), "Compression failed for {}", container);
+        assert!(
+            primed.stdout.len() < plain.stdout.len(),
+            "Dictionary did not help for {}",
+            container
+        );
+        if container == "--zlib" {
+            assert_eq!(primed.stdout[1] & 0x20, 0x20, "FDICT flag not set");
+        }
+
+        let compressed_file = NamedTempFile::new().unwrap();
+        fs::write(compressed_file.path(), &primed.stdout).unwrap();
+        let output = Command::new("./target/debug/gzip")
+            .args(&["-d", "-c", container, "--dict", input])
+            .stdin(File::open(compressed_file.path()).unwrap())
+            .output()
+            .expect("Failed to execute our gzip");
+        assert!(output.status.success(), "Decompression failed for {}", container);
+        assert!(original == output.stdout, "Content mismatch for {}", container);
+    }
+
+    // A zlib stream with a dictionary id cannot be decoded without it
+    let compressed_file = NamedTempFile::new().unwrap();
+    Command::new("./target/debug/gzip")
+        .args(&["-1", "-c", "--zlib", "--dict", input])
+        .stdin(File::open(input).unwrap())
+        .stdout(Stdio::from(compressed_file.reopen().unwrap()))
+        .status()
+        .expect("Failed to execute our gzip");
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-d", "-c", "--zlib"])
+        .stdin(File::open(compressed_file.path()).unwrap())
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(1), "Exit status codes don't match");
+}
+
+#[test]
+fn test_window_bits_and_mem_level() {
+    let input = "tests/test-passage.txt";
+    let original = fs::read(input).unwrap();
+
+    for (window_bits, mem_level) in [("9", "1"), ("11", "4"), ("15", "9")] {
+        let compressed_file = NamedTempFile::new().unwrap();
+        let decompressed_file = NamedTempFile::new().unwrap();
+
+        // Compress with our implementation
+        let status = Command::new("./target/debug/gzip")
+            .args(&["-1", "-c", "--window-bits", window_bits, "--mem-level", mem_level])
+            .stdin(File::open(input).unwrap())
+            .stdout(Stdio::from(compressed_file.reopen().unwrap()))
+            .status()
+            .expect("Failed to execute our gzip");
+        assert!(status.success(), "Compression failed for window bits {}", window_bits);
+
+        // Decompress with system gzip
+        Command::new("gzip")
+            .args(&["-d", "-c"])
+            .stdin(File::open(compressed_file.path()).unwrap())
+            .stdout(Stdio::from(decompressed_file.reopen().unwrap()))
+            .status()
+            .expect("Failed to execute system gzip");
+
+        let decompressed = fs::read(decompressed_file.path()).unwrap();
+        assert!(original == decompressed, "Content mismatch for window bits {}", window_bits);
+    }
+
+    // The zlib header records the window size in CINFO
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c", "--zlib", "--window-bits=10", input])
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.stdout[0], 0x28, "CINFO does not match the window");
+
+    // Out of range values are rejected
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-c", "--window-bits=16", input])
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(1), "Exit status codes don't match");
+}
+
+#[test]
+fn test_long_help() {
+    assert!(compare_gzip_outputs(&["--help"], None));
+    assert!(compare_gzip_outputs(&["--he"], None));
+}
+
+#[test]
+fn test_long_option_errors() {
+    assert!(compare_gzip_outputs(&["--no-such-option"], None));
+    assert!(compare_gzip_outputs(&["--suffix"], None));
+    assert!(compare_gzip_outputs(&["--stdout=yes"], None));
+}
+
+#[test]
+fn test_long_options() {
+    let input = "tests/test-paragraphs.txt";
+
+    // Long forms, abbreviations and `--` give the same output as short options
+    let short = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c", "-n", input])
+        .output()
+        .expect("Failed to execute our gzip");
+    for args in [
+        vec!["--fast", "--stdout", "--no-name", input],
+        vec!["--fa", "--to-std", "--no-n", "--", input],
+        vec!["--stdout", "--suffix", ".z", "--fast", "-n", input],
+    ] {
+        let long = Command::new("./target/debug/gzip")
+            .args(&args)
+            .output()
+            .expect("Failed to execute our gzip");
+        assert!(long.status.success(), "Compression failed for {:?}", args);
+        assert!(short.stdout == long.stdout, "Output mismatch for {:?}", args);
+    }
+
+    // An abbreviation shared by several options is rejected
+    let output = Command::new("./target/debug/gzip")
+        .args(&["--s", input])
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(1), "Exit status codes don't match");
+
+    // Options GNU gzip lacks don't make its abbreviations ambiguous
+    for abbreviation in ["--rec", "--re"] {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let nested = temp_dir.path().join("sub");
+        fs::create_dir(&nested).unwrap();
+        fs::copy(input, nested.join("paragraphs.txt")).unwrap();
+        let status = Command::new("./target/debug/gzip")
+            .args(&[abbreviation, "-1"])
+            .arg(temp_dir.path())
+            .status()
+            .expect("Failed to execute our gzip");
+        assert!(status.success(), "{} should mean --recursive", abbreviation);
+        assert!(nested.join("paragraphs.txt.gz").exists());
+    }
+}
+
+#[test]
+fn test_rsyncable() {
+    let input = "tests/test-passage.txt";
+    let original = fs::read(input).unwrap();
+    let mut changed = original.clone();
+    changed[1000] ^= 0x20;
+
+    let compress = |data: &[u8], extra: &[&str]| {
+        let input_file = NamedTempFile::new().unwrap();
+        fs::write(input_file.path(), data).unwrap();
+        let output = Command::new("./target/debug/gzip")
+            .args(&["-1", "-c", "-n"])
+            .args(extra)
+            .stdin(File::open(input_file.path()).unwrap())
+            .output()
+            .expect("Failed to execute our gzip");
+        assert!(output.status.success(), "Compression failed");
+        output.stdout
+    };
+
+    // The output is a normal gzip stream
+    let compressed_file = NamedTempFile::new().unwrap();
+    fs::write(compressed_file.path(), compress(&original, &["--rsyncable"])).unwrap();
+    let output = Command::new("gzip")
+        .args(&["-d", "-c"])
+        .stdin(File::open(compressed_file.path()).unwrap())
+        .output()
+        .expect("Failed to execute system gzip");
+    assert!(original == output.stdout, "Content mismatch");
+
+    // After a one-byte change, most of the compressed data (all but the
+    // CRC and size trailer) is unchanged
+    let a = compress(&original, &["--rsyncable"]);
+    let b = compress(&changed, &["--rsyncable"]);
+    let (a, b) = (&a[..a.len() - 8], &b[..b.len() - 8]);
+    let common = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    assert!(common > a.len() / 2, "Output did not resynchronise: {} of {} bytes", common, a.len());
+}
+
+#[test]
+fn test_synchronous_recursive() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let sub_dir = temp_dir.path().join("sub");
+    fs::create_dir(&sub_dir).unwrap();
+    let original = fs::read("tests/test-paragraphs.txt").unwrap();
+    fs::write(temp_dir.path().join("a.txt"), &original).unwrap();
+    fs::write(sub_dir.join("b.txt"), &original).unwrap();
+
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-1", "-q", "-r", "--synchronous"])
+        .arg(temp_dir.path())
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success(), "Compression failed");
+
+    for file in [temp_dir.path().join("a.txt"), sub_dir.join("b.txt")] {
+        assert!(!file.exists(), "Input {} should be removed", file.display());
+        let output = Command::new("gzip")
+            .args(&["-d", "-c"])
+            .arg(file.with_extension("txt.gz"))
+            .output()
+            .expect("Failed to execute system gzip");
+        assert!(original == output.stdout, "Content mismatch for {}", file.display());
+    }
+}
+
+#[test]
+fn test_copy_stat() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let file = temp_dir.path().join("secret.txt");
+    let original = fs::read("tests/test-paragraphs.txt").unwrap();
+    fs::write(&file, &original).unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+    let mtime = filetime::FileTime::from_unix_time(1_577_934_245, 123_456_789);
+    let atime = filetime::FileTime::from_unix_time(1_577_934_000, 987_654_321);
+    filetime::set_file_times(&file, atime, mtime).unwrap();
+
+    let check = |path: &Path| {
+        let metadata = fs::metadata(path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600, "Mode of {}", path.display());
+        assert_eq!(filetime::FileTime::from_last_modification_time(&metadata), mtime);
+        assert_eq!(filetime::FileTime::from_last_access_time(&metadata), atime);
+    };
+
+    // Both directions take the metadata from the input file
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-1", "-q"])
+        .arg(&file)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success(), "Compression failed");
+    let compressed = file.with_extension("txt.gz");
+    check(&compressed);
+
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-d", "-q"])
+        .arg(&compressed)
+        .stdout(Stdio::null())
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success(), "Decompression failed");
+    check(&file);
+    assert!(original == fs::read(&file).unwrap(), "Content mismatch");
+}
+
+// Poll until cond holds, failing the test if it takes more than 10 seconds.
+fn wait_until(what: &str, mut cond: impl FnMut() -> bool) {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !cond() {
+        assert!(std::time::Instant::now() < deadline, "Timed out waiting for {}", what);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_signal_removes_partial_output() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let input = temp_dir.path().join("big");
+    let passage = fs::read("tests/test-passage.txt").unwrap();
+    fs::write(&input, passage.repeat(100)).unwrap();
+    let entries = || fs::read_dir(temp_dir.path()).unwrap().count();
+
+    let mut child = Command::new("./target/debug/gzip")
+        .args(&["-1", "-q"])
+        .arg(&input)
+        .spawn()
+        .expect("Failed to execute our gzip");
+    wait_until("gzip to create its output", || entries() >= 2);
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    let status = child.wait().unwrap();
+
+    assert_eq!(status.signal(), Some(libc::SIGTERM), "gzip should die from the signal");
+    assert_eq!(entries(), 1, "Partial output should be removed");
+    assert!(input.exists(), "Input should be untouched");
+}
+
+#[test]
+fn test_atomic_output() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let input = temp_dir.path().join("big");
+    let passage = fs::read("tests/test-passage.txt").unwrap();
+    fs::write(&input, passage.repeat(100)).unwrap();
+    let output = temp_dir.path().join("big.gz");
+    fs::write(&output, b"previous good output").unwrap();
+
+    // While gzip -f writes its temporary file, the existing output stays as
+    // it was. gzip is stopped so it can't finish while we look.
+    let writing = || {
+        fs::read_dir(temp_dir.path())
+            .unwrap()
+            .any(|entry| entry.unwrap().file_name().to_string_lossy().starts_with(".big."))
+    };
+    let mut child = Command::new("./target/debug/gzip")
+        .args(&["-1", "-f", "-q"])
+        .arg(&input)
+        .spawn()
+        .expect("Failed to execute our gzip");
+    wait_until("gzip to create its temporary file", writing);
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGSTOP);
+    }
+    assert!(writing(), "gzip finished before it could be stopped");
+    assert_eq!(fs::read(&output).unwrap(), b"previous good output");
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+        libc::kill(child.id() as libc::pid_t, libc::SIGCONT);
+    }
+    child.wait().unwrap();
+    assert_eq!(fs::read(&output).unwrap(), b"previous good output");
+    assert!(input.exists(), "Input should be untouched");
+
+    // A completed run replaces it and leaves no temporary file behind
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-1", "-f", "-q"])
+        .arg(&input)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success(), "Compression failed");
+    assert!(!input.exists(), "Input should be removed");
+    let names: Vec<_> = fs::read_dir(temp_dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names, vec![std::ffi::OsString::from("big.gz")]);
+    let output = Command::new("gzip")
+        .args(&["-d", "-c"])
+        .arg(temp_dir.path().join("big.gz"))
+        .output()
+        .expect("Failed to execute system gzip");
+    assert!(passage.repeat(100) == output.stdout, "Content mismatch");
+}
+
+#[test]
+fn test_list() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let input = temp_dir.path().join("passage.txt");
+    fs::copy("tests/test-passage.txt", &input).unwrap();
+    let status = Command::new("gzip")
+        .args(&["-k"])
+        .arg(&input)
+        .status()
+        .expect("Failed to execute system gzip");
+    assert!(status.success(), "Compression failed");
+    let compressed = temp_dir.path().join("passage.txt.gz");
+
+    // A single member is described by its trailer, like GNU gzip does
+    for args in [&["-l"][..], &["-lv"][..], &["-lvN"][..]] {
+        let output = Command::new("./target/debug/gzip")
+            .args(args)
+            .arg(&compressed)
+            .output()
+            .expect("Failed to execute our gzip");
+        let system_output = Command::new("gzip")
+            .args(args)
+            .arg(&compressed)
+            .output()
+            .expect("Failed to execute system gzip");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&system_output.stdout),
+            "Listing mismatch for {:?}",
+            args
+        );
+    }
+
+    // Several members read from a pipe are decoded and added up
+    let member = fs::read(&compressed).unwrap();
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg("cat \"$1\" \"$1\" | ./target/debug/gzip -l")
+        .arg("sh")
+        .arg(&compressed)
+        .output()
+        .expect("Failed to execute our gzip");
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = listing.lines().nth(1).unwrap().split_whitespace().collect();
+    let original_len = fs::metadata(&input).unwrap().len();
+    assert_eq!(fields[0], (2 * member.len()).to_string());
+    assert_eq!(fields[1], (2 * original_len).to_string());
+}
+
+#[test]
+fn test_list_multi_member_file() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let output = Command::new("gzip")
+        .args(&["-c", "tests/test-passage.txt"])
+        .output()
+        .expect("Failed to execute system gzip");
+    assert!(output.status.success(), "Compression failed");
+    let member = output.stdout;
+    let compressed = temp_dir.path().join("twice.gz");
+    fs::write(&compressed, [&member[..], &member[..]].concat()).unwrap();
+
+    // Plain -l trusts the trailer, which only describes the last member, as
+    // GNU gzip does. Values checked by decoding add both members up, as
+    // when the same bytes come from a pipe.
+    let original_len = fs::metadata("tests/test-passage.txt").unwrap().len();
+    let system = Command::new("gzip")
+        .arg("-l")
+        .arg(&compressed)
+        .output()
+        .expect("Failed to execute system gzip");
+    let output = Command::new("./target/debug/gzip")
+        .arg("-l")
+        .arg(&compressed)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert!(output.status.success());
+    let sizes = |listing: &[u8]| -> Vec<String> {
+        let line = String::from_utf8_lossy(listing).lines().nth(1).unwrap().to_string();
+        line.split_whitespace().take(2).map(String::from).collect()
+    };
+    assert_eq!(sizes(&output.stdout), sizes(&system.stdout));
+    assert_eq!(sizes(&output.stdout)[1], original_len.to_string());
+
+    let output = Command::new("./target/debug/gzip")
+        .arg("-lv")
+        .arg(&compressed)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert!(output.status.success());
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = listing.lines().nth(1).unwrap().split_whitespace().collect();
+    assert_eq!(fields[5], (2 * member.len()).to_string());
+    assert_eq!(fields[6], (2 * original_len).to_string());
+
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-l", "--format=json"])
+        .arg(&compressed)
+        .output()
+        .expect("Failed to execute our gzip");
+    let record: serde_json::Value =
+        serde_json::from_str(String::from_utf8_lossy(&output.stdout).lines().next().unwrap()).unwrap();
+    assert_eq!(record["uncompressed"], 2 * original_len);
+}
+
+#[test]
+fn test_integrity_check() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let good = temp_dir.path().join("good.gz");
+    let output = Command::new("gzip")
+        .args(&["-c", "tests/test-passage.txt"])
+        .output()
+        .expect("Failed to execute system gzip");
+    fs::write(&good, &output.stdout).unwrap();
+    let mut data = output.stdout.clone();
+    let len = data.len();
+    data[len - 8] ^= 1;
+    fs::write(temp_dir.path().join("crc.gz"), &data).unwrap();
+    fs::write(temp_dir.path().join("short.gz"), &output.stdout[..len / 2]).unwrap();
+    let listing = || {
+        let mut names: Vec<_> = fs::read_dir(temp_dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        names
+    };
+    let before = listing();
+
+    // Every file is tested and reported, and nothing is written anywhere
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-t", "-v"])
+        .args(["crc.gz", "short.gz", "good.gz"].iter().map(|name| temp_dir.path().join(name)))
+        .output()
+        .expect("Failed to execute our gzip");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "Failures should set the exit status");
+    assert!(output.stdout.is_empty(), "Test mode should not write any output");
+    assert!(stderr.contains("crc.gz: invalid compressed data--crc error"), "{}", stderr);
+    assert!(stderr.contains("short.gz: unexpected end of file"), "{}", stderr);
+    assert!(stderr.contains("good.gz:\t OK"), "{}", stderr);
+    assert_eq!(listing(), before, "Test mode should not create or remove files");
+
+    let status = Command::new("./target/debug/gzip")
+        .arg("-t")
+        .arg(&good)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success(), "A good file should pass");
+}
+
+#[test]
+fn test_gzip_env_options() {
+    let compress = |gzip_env: &str, args: &[&str]| {
+        Command::new("./target/debug/gzip")
+            .args(args)
+            .env("GZIP", gzip_env)
+            .stdin(File::open("tests/test-passage.txt").unwrap())
+            .output()
+            .expect("Failed to execute our gzip")
+    };
+    let plain = |args: &[&str]| compress("", args).stdout;
+
+    // Allowed options apply, with a deprecation warning unless quiet
+    let output = compress("-n --rsyncable", &["-1", "-c"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, plain(&["-1", "-c", "-n", "--rsyncable"]));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "gzip: warning: GZIP environment variable is deprecated; use an alias or script\n"
+    );
+    assert!(compress("-q -3", &["-c"]).stderr.is_empty());
+
+    // The command line takes precedence
+    assert_eq!(compress("-q -3", &["-1", "-c"]).stdout, plain(&["-1", "-c"]));
+
+    // Options that select files or output are rejected
+    for (gzip_env, option) in [("-n -k", "-k"), ("--suffix=.x", "--suffix"), ("--std", "--stdout")] {
+        let output = compress(gzip_env, &["-1", "-c"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            format!(
+                "gzip: {}: option not valid in GZIP environment variable\nTry `gzip --help' for more information.\n",
+                option
+            )
+        );
+    }
+    let output = compress("-1 file", &["-c"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("gzip: file: non-option in GZIP environment variable\n"));
+}
+
+#[test]
+fn test_recursive_traversal() {
+    use std::os::unix::fs::PermissionsExt;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let root = temp_dir.path().join("tree");
+    let outside = temp_dir.path().join("outside");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::create_dir(&outside).unwrap();
+    fs::copy("tests/test-passage.txt", root.join("a")).unwrap();
+    fs::copy("tests/test-passage.txt", root.join("sub/b")).unwrap();
+    fs::copy("tests/test-passage.txt", outside.join("o")).unwrap();
+    fs::write(root.join("done.gz"), b"not really compressed").unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+    // Paths longer than the old 1024 byte limit still work
+    let mut deep = root.clone();
+    while deep.as_os_str().len() < 1200 {
+        deep.push("d".repeat(100));
+    }
+    fs::create_dir_all(&deep).unwrap();
+    fs::copy("tests/test-passage.txt", deep.join("deep")).unwrap();
+
+    // An unreadable directory is reported without stopping the traversal
+    let locked = root.join("locked");
+    fs::create_dir(&locked).unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    let locked_readable = fs::read_dir(&locked).is_ok();
+
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-r", "-1"])
+        .arg(&root)
+        .output()
+        .expect("Failed to execute our gzip");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(root.join("a.gz").exists() && !root.join("a").exists());
+    assert!(root.join("sub/b.gz").exists());
+    assert!(deep.join("deep.gz").exists(), "Long paths should be handled");
+    assert!(root.join("done.gz").exists() && !root.join("done.gz.gz").exists());
+    assert!(!stderr.contains("already has"), "-r should skip compressed files quietly: {}", stderr);
+    assert!(outside.join("o").exists() && !outside.join("o.gz").exists(), "Symlinked directories should not be followed");
+    if locked_readable {
+        // Running as root: permissions don't apply
+        assert!(output.status.success(), "{}", stderr);
+    } else {
+        assert_eq!(output.status.code(), Some(1), "{}", stderr);
+        assert!(stderr.contains("locked"), "{}", stderr);
+    }
+
+    // Outside of -r an already compressed file is reported, but like GNU
+    // gzip it does not change the exit status
+    let output = Command::new("./target/debug/gzip")
+        .arg(root.join("done.gz"))
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already has .gz suffix -- unchanged"));
+}
+
+#[test]
+fn test_non_utf8_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let latin1 = OsStr::from_bytes(b"caf\xe9");
+    let input = temp_dir.path().join(latin1);
+    fs::copy("tests/test-passage.txt", &input).unwrap();
+
+    let status = Command::new("./target/debug/gzip")
+        .arg("-1")
+        .arg(&input)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success());
+    let compressed = temp_dir.path().join(OsStr::from_bytes(b"caf\xe9.gz"));
+    let data = fs::read(&compressed).unwrap();
+    assert!(data.windows(5).any(|w| w == b"caf\xe9\0"), "The stored name should be the raw bytes");
+
+    // -N restores the stored name verbatim, whatever the input is called
+    let renamed = temp_dir.path().join("renamed.gz");
+    fs::rename(&compressed, &renamed).unwrap();
+    let output = Command::new("./target/debug/gzip")
+        .arg("-lN")
+        .arg(&renamed)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert!(output.stdout.ends_with(b"caf\xe9\n"), "{:?}", String::from_utf8_lossy(&output.stdout));
+    let status = Command::new("./target/debug/gzip")
+        .arg("-dN")
+        .arg(&renamed)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success());
+    assert_eq!(fs::read(&input).unwrap(), fs::read("tests/test-passage.txt").unwrap());
+
+    // Recursive mode reaches entries with such names too
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-r", "-1"])
+        .arg(temp_dir.path())
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success());
+    assert!(compressed.exists() && !input.exists());
+}
+
+#[test]
+fn test_progress() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let input = temp_dir.path().join("passage.txt");
+    fs::copy("tests/test-passage.txt", &input).unwrap();
+
+    // Without a terminal on stderr --progress stays silent
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c", "--progress"])
+        .arg(&input)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    let plain = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c"])
+        .arg(&input)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.stdout, plain.stdout);
+
+    // On a terminal the status line ends at 100% of a regular file
+    let command = format!("./target/debug/gzip -1 --progress {}", input.display());
+    let output = match Command::new("script").args(&["-qec", &command, "/dev/null"]).output() {
+        Ok(output) => output,
+        Err(_) => return, // no way to get a terminal here
+    };
+    assert!(output.status.success());
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(shown.contains("passage.txt: 73.3 KiB 100.0%"), "{}", shown);
+    assert!(temp_dir.path().join("passage.txt.gz").exists());
+}
+
+#[test]
+fn test_json_format() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let good = temp_dir.path().join("good.gz");
+    let bad = temp_dir.path().join("bad.gz");
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c", "tests/test-passage.txt"])
+        .output()
+        .expect("Failed to execute our gzip");
+    fs::write(&good, &output.stdout).unwrap();
+    let mut data = output.stdout.clone();
+    let crc_offset = data.len() - 8;
+    data[crc_offset] ^= 0xff;
+    fs::write(&bad, &data).unwrap();
+    let size = fs::metadata("tests/test-passage.txt").unwrap().len();
+
+    let records = |args: &[&str]| -> Vec<serde_json::Value> {
+        let output = Command::new("./target/debug/gzip")
+            .args(args)
+            .args(&[&good, &bad])
+            .output()
+            .expect("Failed to execute our gzip");
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Each line should be a JSON object"))
+            .collect()
+    };
+
+    let list = records(&["-l", "--format=json"]);
+    assert_eq!(list.len(), 3, "{:?}", list);
+    assert_eq!(list[0]["name"], good.to_str().unwrap());
+    assert_eq!(list[0]["method"], "deflate");
+    assert_eq!(list[0]["compressed"], output.stdout.len() as u64);
+    assert_eq!(list[0]["uncompressed"], size);
+    assert!(list[0]["crc"].is_u64() && list[0]["ratio"].is_f64());
+    assert_eq!(list[2]["totals"]["files"], 2);
+    assert_eq!(list[2]["totals"]["uncompressed"], 2 * size);
+
+    let test = records(&["-t", "--format=json"]);
+    assert_eq!(test.len(), 3, "{:?}", test);
+    assert_eq!(test[0]["test"], "ok");
+    assert!(test[0]["error"].is_null());
+    assert_eq!(test[1]["test"], "failed");
+    assert_eq!(test[1]["error"], "invalid compressed data--crc error");
+    assert_eq!(test[2]["totals"]["errors"], 1);
+
+    // A multi-member file still gets one record, with the crc of all of its
+    // output: the same as in the trailer of a single member holding it all
+    let twice = temp_dir.path().join("twice.gz");
+    fs::write(&twice, [&output.stdout[..], &output.stdout[..]].concat()).unwrap();
+    let passage = fs::read("tests/test-passage.txt").unwrap();
+    let mut child = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute our gzip");
+    child.stdin.take().unwrap().write_all(&[&passage[..], &passage[..]].concat()).unwrap();
+    let whole = child.wait_with_output().unwrap().stdout;
+    let whole_crc = u32::from_le_bytes(whole[whole.len() - 8..whole.len() - 4].try_into().unwrap());
+    for args in [&["-l", "--format=json"], &["-t", "--format=json"]] {
+        let output = Command::new("./target/debug/gzip")
+            .args(args)
+            .arg(&twice)
+            .output()
+            .expect("Failed to execute our gzip");
+        let lines: Vec<&str> = std::str::from_utf8(&output.stdout).unwrap().lines().collect();
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(record["crc"], whole_crc, "{:?}", args);
+        assert_eq!(record["uncompressed"], 2 * size);
+    }
+
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-l", "--format=xml"])
+        .arg(&good)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert_eq!(status.code(), Some(1));
+}
+
+#[test]
+fn test_recover() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let damaged = temp_dir.path().join("damaged.gz");
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-1", "-n", "-c", "tests/test-passage.txt"])
+        .output()
+        .expect("Failed to execute our gzip");
+    // Give the first block of the first member the reserved block type, and
+    // follow it with an intact member
+    let mut data = output.stdout.clone();
+    data[10] |= 0x06;
+    data.extend_from_slice(&output.stdout);
+    fs::write(&damaged, &data).unwrap();
+
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-d", "-q"])
+        .arg(&damaged)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert_eq!(status.code(), Some(1));
+
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-d", "--recover"])
+        .arg(&damaged)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("damaged data at bytes 10-"), "{}", stderr);
+    assert!(damaged.exists(), "Damaged input should be kept");
+    let recovered = fs::read(temp_dir.path().join("damaged")).unwrap();
+    assert_eq!(recovered, fs::read("tests/test-passage.txt").unwrap());
+
+    // The JSON record tells a recovered file from an intact one
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-t", "-q", "--recover", "--format=json"])
+        .arg(&damaged)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(2));
+    let records: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2, "{:?}", records);
+    assert_eq!(records[0]["test"], "damaged");
+    assert_eq!(records[0]["damaged"][0][0], 10);
+    assert!(records[0]["error"].is_null());
+    assert_eq!(records[1]["totals"]["damaged"], 1);
+}
+
+#[test]
+fn test_output_limits() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let zeros = temp_dir.path().join("zeros");
+    fs::write(&zeros, vec![0u8; 1 << 20]).unwrap();
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-1"])
+        .arg(&zeros)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success());
+    let compressed = zeros.with_extension("gz");
+    let compressed_len = fs::metadata(&compressed).unwrap().len() as usize;
+
+    let decompress = |args: &[&str]| {
+        Command::new("./target/debug/gzip")
+            .args(&["-d", "-c"])
+            .args(args)
+            .arg(&compressed)
+            .output()
+            .expect("Failed to execute our gzip")
+    };
+
+    let output = decompress(&["--max-output=64k"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout.len(), 64 * 1024);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("output exceeds the --max-output limit"), "{}", stderr);
+
+    let output = decompress(&["--max-ratio=10"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.len() <= 10 * compressed_len);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("expansion ratio exceeds the --max-ratio limit"), "{}", stderr);
+
+    let output = decompress(&["--max-output=1M", "--max-ratio=10000"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 1 << 20);
+
+    // The output file is not left behind
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-d", "-q", "--max-output=1000"])
+        .arg(&compressed)
+        .status()
+        .expect("Failed to execute our gzip");
+    assert_eq!(status.code(), Some(1));
+    assert!(compressed.exists());
+    assert!(!zeros.exists());
+}
+
+#[test]
+fn test_verify() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let input = temp_dir.path().join("passage.txt");
+    fs::copy("tests/test-passage.txt", &input).unwrap();
+    let expected = fs::read(&input).unwrap();
+
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c", "--verify"])
+        .arg(&input)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let compressed = temp_dir.path().join("stdout.gz");
+    fs::write(&compressed, &output.stdout).unwrap();
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-d", "-c"])
+        .arg(&compressed)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.stdout, expected);
+
+    let output = Command::new("./target/debug/gzip")
+        .args(&["-1", "--zlib", "--verify"])
+        .arg(&input)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!input.exists());
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-d", "--zlib"])
+        .arg(temp_dir.path().join("passage.txt.zz"))
+        .status()
+        .expect("Failed to execute our gzip");
+    assert!(status.success());
+    assert_eq!(fs::read(&input).unwrap(), expected);
+}
+
+#[test]
+fn test_verify_failure() {
+    use super::{GzipState, DEFLATED, ERROR};
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let input = temp_dir.path().join("passage.txt");
+    fs::copy("tests/test-passage.txt", &input).unwrap();
+    let member = Command::new("./target/debug/gzip")
+        .args(&["-1", "-c"])
+        .arg(&input)
+        .output()
+        .expect("Failed to execute our gzip")
+        .stdout;
+    let mut damaged = member.clone();
+    let middle = damaged.len() / 2;
+    damaged[middle] ^= 0xff;
+
+    // As zip leaves things: the output written, with the crc and length of
+    // the input to check it against
+    let new_state = || {
+        let mut state = GzipState::new();
+        state.verify = true;
+        state.method = DEFLATED;
+        state.ifname = input.clone();
+        state.ofname = temp_dir.path().join("passage.txt.gz");
+        state.tmp_ofname = temp_dir.path().join(".passage.txt.gz.tmp");
+        state.crc = u32::from_le_bytes(member[member.len() - 8..member.len() - 4].try_into().unwrap());
+        state.bytes_in = fs::metadata(&input).unwrap().len() as i64;
+        state
+    };
+
+    // Output file: damaged output fails the file, which treat_file then
+    // removes, keeping the input
+    let mut state = new_state();
+    fs::write(&state.tmp_ofname, &member).unwrap();
+    assert!(state.verify_compressed(None).unwrap());
+    fs::write(&state.tmp_ofname, &damaged).unwrap();
+    assert!(!state.verify_compressed(None).unwrap());
+    assert_eq!(state.exit_code, ERROR);
+    state.remove_output_file().unwrap();
+    assert!(input.exists());
+    assert!(!state.tmp_ofname.exists() && !state.ofname.exists());
+
+    // Standard output: the data is checked as it is written
+    for (data, ok) in [(&member, true), (&damaged, false)] {
+        let mut state = new_state();
+        state.to_stdout = true;
+        state.ofd = Some(Box::new(std::io::sink()));
+        let decoder = state.tee_for_verify();
+        assert!(decoder.is_some());
+        for chunk in data.chunks(1000) {
+            state.ofd.as_mut().unwrap().write_all(chunk).unwrap();
+        }
+        assert_eq!(state.verify_compressed(decoder).unwrap(), ok);
+        assert_eq!(state.exit_code == ERROR, !ok);
+    }
+}
+
+#[test]
+fn test_trailing_data() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let member = Command::new("./target/debug/gzip")
+        .args(&["-1", "-n", "-c", "tests/test-passage.txt"])
+        .output()
+        .expect("Failed to execute our gzip")
+        .stdout;
+    let trailing = b"TAPE\x00PADDING";
+    let padded = temp_dir.path().join("padded.gz");
+    let mut data = member.clone();
+    data.extend_from_slice(trailing);
+    fs::write(&padded, &data).unwrap();
+    let expected = fs::read("tests/test-passage.txt").unwrap();
+
+    let decompress = |args: &[&str]| {
+        Command::new("./target/debug/gzip")
+            .args(&["-d", "-c"])
+            .args(args)
+            .arg(&padded)
+            .output()
+            .expect("Failed to execute our gzip")
+    };
+
+    let output = decompress(&[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("trailing garbage ignored"));
+    assert_eq!(output.stdout, expected);
+
+    let output = decompress(&["--trailing=ignore"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stderr.is_empty());
+    assert_eq!(output.stdout, expected);
+
+    let output = decompress(&["--trailing=error"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("trailing data at byte {}", member.len())), "{}", stderr);
+
+    let dump = temp_dir.path().join("trailing.bin");
+    let output = decompress(&[&format!("--trailing-dump={}", dump.display())]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, expected);
+    assert_eq!(fs::read(&dump).unwrap(), trailing);
+
+    let output = decompress(&["-t", "--format=json"]);
+    let record: serde_json::Value =
+        serde_json::from_str(String::from_utf8_lossy(&output.stdout).lines().next().unwrap()).unwrap();
+    assert_eq!(record["trailing"], member.len() as u64);
+}
+
+#[test]
+fn test_reproducible() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let first = temp_dir.path().join("first.txt");
+    let second = temp_dir.path().join("second.txt");
+    fs::copy("tests/test-passage.txt", &first).unwrap();
+    fs::copy("tests/test-passage.txt", &second).unwrap();
+    filetime::set_file_mtime(&first, filetime::FileTime::from_unix_time(1000, 0)).unwrap();
+    filetime::set_file_mtime(&second, filetime::FileTime::from_unix_time(2_000_000_000, 0)).unwrap();
+
+    let compress = |input: &Path, epoch: Option<&str>| {
+        let mut command = Command::new("./target/debug/gzip");
+        command.args(&["-1", "-c", "--reproducible"]).arg(input).env_remove("SOURCE_DATE_EPOCH");
+        if let Some(epoch) = epoch {
+            command.env("SOURCE_DATE_EPOCH", epoch);
+        }
+        let output = command.output().expect("Failed to execute our gzip");
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    // Name, mtime and build machine leave no trace: fixed OS byte, no name,
+    // and the time stamp clamped to SOURCE_DATE_EPOCH
+    let output = compress(&first, None);
+    assert_eq!(output[..10], [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 4, 0xff]);
+    let output = compress(&second, Some("1500000000"));
+    assert_eq!(output[4..8], 1_500_000_000u32.to_le_bytes());
+    assert_eq!(compress(&first, Some("1500000000"))[4..8], 1000u32.to_le_bytes());
+
+    let from_stdin = Command::new("./target/debug/gzip")
+        .args(&["-1", "--reproducible"])
+        .env("SOURCE_DATE_EPOCH", "1500000000")
+        .stdin(File::open(&second).unwrap())
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(from_stdin.stdout, output);
+
+    let status = Command::new("./target/debug/gzip")
+        .args(&["-1", "--reproducible"])
+        .arg(&first)
+        .env("SOURCE_DATE_EPOCH", "yesterday")
+        .status()
+        .expect("Failed to execute our gzip");
+    assert_eq!(status.code(), Some(1));
+}
+
+#[test]
+fn test_join() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let passage = fs::read("tests/test-passage.txt").unwrap();
+    let mut names = Vec::new();
+    let mut expected = Vec::new();
+    for (i, (data, level)) in [(&passage[..1000], "-1"), (&passage[..], "-3")].iter().enumerate() {
+        let name = temp_dir.path().join(format!("part{}", i));
+        fs::write(&name, data).unwrap();
+        let status = Command::new("./target/debug/gzip")
+            .arg(level)
+            .arg(&name)
+            .status()
+            .expect("Failed to execute our gzip");
+        assert!(status.success());
+        names.push(name.with_extension("gz"));
+        expected.extend_from_slice(data);
+    }
+    // Members from system gzip: an empty one, and one with a stored block
+    let random: Vec<u8> = (0..5000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+    for (i, data) in [&[][..], &random[..]].iter().enumerate() {
+        let name = temp_dir.path().join(format!("system{}.gz", i));
+        let status = Command::new("gzip")
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::from(File::create(&name).unwrap()))
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(data)?;
+                child.wait()
+            })
+            .expect("Failed to execute system gzip");
+        assert!(status.success());
+        names.push(name);
+        expected.extend_from_slice(data);
+    }
+
+    let joined = Command::new("./target/debug/gzip")
+        .arg("--join")
+        .args(&names)
+        .output()
+        .expect("Failed to execute our gzip");
+    assert!(joined.status.success(), "{}", String::from_utf8_lossy(&joined.stderr));
+    // A single member: its trailer covers all the data
+    let len = joined.stdout.len();
+    assert_eq!(joined.stdout[len - 4..], (expected.len() as u32).to_le_bytes());
+
+    let joined_name = temp_dir.path().join("joined.gz");
+    fs::write(&joined_name, &joined.stdout).unwrap();
+    let output = Command::new("gzip")
+        .args(&["-d", "-c"])
+        .arg(&joined_name)
+        .output()
+        .expect("Failed to execute system gzip");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+
+    // Nothing is written when an input is not a gzip file
+    let output = Command::new("./target/debug/gzip")
+        .arg("--join")
+        .arg(&names[0])
+        .arg("tests/test-passage.txt")
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_empty_input_compression() {
+    // An empty file still gets a final block that system gzip accepts
+    for level in ["-1", "-3"] {
+        let compressed = Command::new("./target/debug/gzip")
+            .args(&[level, "-c", "tests/test-empty.txt"])
+            .output()
+            .expect("Failed to execute our gzip");
+        assert!(compressed.status.success());
+        let output = Command::new("gzip")
+            .args(&["-d", "-c"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(&compressed.stdout)?;
+                child.wait_with_output()
+            })
+            .expect("Failed to execute system gzip");
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[test]
+fn test_append() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let passage = fs::read("tests/test-passage.txt").unwrap();
+    let target = temp_dir.path().join("log.gz");
+    let part = temp_dir.path().join("part");
+    let mut expected = Vec::new();
+
+    // The first append creates the file, the last one adds nothing
+    for data in [&passage[..3000], &passage[..], &[][..]] {
+        fs::write(&part, data).unwrap();
+        let output = Command::new("./target/debug/gzip")
+            .arg("-1")
+            .arg(format!("--append={}", target.display()))
+            .arg(&part)
+            .output()
+            .expect("Failed to execute our gzip");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        expected.extend_from_slice(data);
+    }
+    // Data from stdin
+    let status = Command::new("./target/debug/gzip")
+        .arg("-3")
+        .arg(format!("--append={}", target.display()))
+        .stdin(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(&passage[..500])?;
+            child.wait()
+        })
+        .expect("Failed to execute our gzip");
+    assert!(status.success());
+    expected.extend_from_slice(&passage[..500]);
+    assert!(part.exists());
+
+    // Still a single member: its trailer covers all the data
+    let compressed = fs::read(&target).unwrap();
+    let len = compressed.len();
+    assert_eq!(compressed[len - 4..], (expected.len() as u32).to_le_bytes());
+    let output = Command::new("gzip")
+        .args(&["-d", "-c"])
+        .arg(&target)
+        .output()
+        .expect("Failed to execute system gzip");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+
+    // A file that is not gzip is left alone
+    fs::write(&part, &passage[..100]).unwrap();
+    let output = Command::new("./target/debug/gzip")
+        .arg(format!("--append={}", part.display()))
+        .arg("tests/test-passage.txt")
+        .output()
+        .expect("Failed to execute our gzip");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(fs::read(&part).unwrap(), &passage[..100]);
+}
@@ -1,4 +1,4 @@
-use crate::deflate::{Deflate, MAX_DIST, MAX_MATCH, MIN_MATCH};
+use crate::deflate::{Deflate, Strategy, DEF_MEM_LEVEL, MAX_DIST, MAX_MATCH, MIN_MATCH};
 use crate::{GzipState, STORED};
 use std::cell::RefCell;
 use std::ops::{Deref, DerefMut};
@@ -8,8 +8,6 @@ const MAX_BITS: usize = 15;
 const MAX_BL_BITS: usize = 7;
 const LENGTH_CODES: usize = 29;
 const LITERALS: usize = 256;
-const LIT_BUFSIZE: usize = 0x8000;
-const DIST_BUFSIZE: usize = 0x8000;
 const L_CODES: usize = LITERALS + 1 + LENGTH_CODES;
 const D_CODES: usize = 30;
 const BL_CODES: usize = 19;
@@ -64,7 +62,7 @@ enum TreeType {
 
 #[derive(Default, Copy, Clone, Debug)]
 pub struct CtData {
-    freq: u16,
+    freq: u32,
     len: u16,
     code: u16,
     dad: u16
@@ -93,16 +91,18 @@ pub struct Trees<'a> {
     pub last_flags: i32,
     pub flags: u8,
     pub flag_bit: u8,
-    pub l_buf: Box<[usize; LIT_BUFSIZE]>,
-    pub d_buf: Box<[usize; DIST_BUFSIZE]>,
-    pub flag_buf: Box<[usize; LIT_BUFSIZE/8]>,
+    pub l_buf: Vec<u8>,         // literals or match lengths - MIN_MATCH
+    pub d_buf: Vec<u16>,        // match distances - 1
+    pub flag_buf: Vec<u8>,      // one bit per l_buf entry: set for a match
+    lit_bufsize: usize,
     pub l_desc: TreeDesc<'a>,
     pub d_desc: TreeDesc<'a>,
     pub bl_desc: TreeDesc<'a>,
     pub heap: [i32; 2*L_CODES+1],
     pub depth: [i32; 2*L_CODES+1],
     pub heap_len: usize,
-    pub heap_max: usize
+    pub heap_max: usize,
+    pub strategy: Strategy,
 }
 
 #[derive(Clone)]
@@ -121,8 +121,20 @@ enum TreeKind {
     Dynamic,
 }
 
+/// Symbols buffered per block for a memory level: 32K at the default level.
+pub(crate) fn lit_bufsize(mem_level: u32) -> usize {
+    1 << (mem_level + 7)
+}
+
 impl<'a> Trees<'a> {
     pub fn new() -> Self {
+        Self::with_mem_level(DEF_MEM_LEVEL)
+    }
+
+    /// Create the trees with a literal buffer sized by the memory level
+    /// (1..=9), which bounds the number of symbols per block.
+    pub fn with_mem_level(mem_level: u32) -> Self {
+        let lit_bufsize = lit_bufsize(mem_level);
         let static_ltree = vec![CtData::default(); L_CODES + 2];
         let static_dtree = vec![CtData::default(); D_CODES];
         let bltree = vec![CtData::default(); 2 * BL_CODES + 1];
@@ -152,9 +164,10 @@ impl<'a> Trees<'a> {
             last_flags: 0,
             flags: 0,
             flag_bit: 1,
-            l_buf: Box::new([0; LIT_BUFSIZE]),
-            d_buf: Box::new([0; DIST_BUFSIZE]),
-            flag_buf: Box::new([0; LIT_BUFSIZE/8]),
+            l_buf: vec![0; lit_bufsize],
+            d_buf: vec![0; lit_bufsize],
+            flag_buf: vec![0; lit_bufsize / 8],
+            lit_bufsize,
             l_desc: TreeDesc {
                 tree_type: TreeType::Literal,
                 // dyn_tree: dyn_ltree,
@@ -188,7 +201,8 @@ impl<'a> Trees<'a> {
             heap: [0; 2*L_CODES+1],
             depth: [0; 2*L_CODES+1],
             heap_len: 0,
-            heap_max: 0
+            heap_max: 0,
+            strategy: Strategy::Default,
         }
     }
 
@@ -412,8 +426,7 @@ impl<'a> Trees<'a> {
         // println!("lc: {}", lc);
         
         // Add the character or match length to the literal buffer
-        state.inbuf[self.last_lit as usize] = lc as u8 ;
-        // self.l_buf[self.last_lit as usize] = lc as usize ;
+        self.l_buf[self.last_lit as usize] = lc as u8;
 
         self.last_lit += 1;
         
@@ -439,7 +452,7 @@ impl<'a> Trees<'a> {
             self.dyn_ltree[length_index].freq += 1;
             self.dyn_dtree[dist_index].freq += 1;
 
-            self.d_buf[self.last_dist as usize] = dist as u16 as usize;
+            self.d_buf[self.last_dist as usize] = dist as u16;
             self.last_dist += 1;
             self.flags |= self.flag_bit;
             // println!("dist: {}", dist);
@@ -452,7 +465,7 @@ impl<'a> Trees<'a> {
 
         // Output the flags if they fill a byte
         if (self.last_lit & 7) == 0 {
-            self.flag_buf[self.last_flags as usize] = self.flags as usize;
+            self.flag_buf[self.last_flags as usize] = self.flags;
             self.last_flags += 1;
             self.flags = 0;
             self.flag_bit = 1;
@@ -463,7 +476,7 @@ impl<'a> Trees<'a> {
         if state.level > 2 && (self.last_lit & 0xfff) == 0 {
             // Compute an upper bound for the compressed length
             let mut out_length = self.last_lit as u64 * 8;
-            let in_length = deflate.strstart - deflate.block_start as usize;
+            let in_length = (deflate.strstart as i64 - deflate.block_start) as u64;
 
             for dcode in 0..D_CODES {
                 out_length += self.dyn_dtree[dcode].freq as u64
@@ -479,18 +492,18 @@ impl<'a> Trees<'a> {
                     self.last_dist,
                     in_length,
                     out_length,
-                    100 - out_length * 100 / in_length as u64
+                    100 - out_length as i64 * 100 / in_length as i64
                 );
             }
 
-            if self.last_dist < self.last_lit / 2 && out_length < (in_length / 2) as u64 {
+            if self.last_dist < self.last_lit / 2 && out_length < in_length / 2 {
                 return true;
             }
         }
 
 
         // Return true if the buffer is full
-        self.last_lit == (LIT_BUFSIZE - 1) as i32 || self.last_dist == DIST_BUFSIZE as i32
+        self.last_lit == (self.lit_bufsize - 1) as i32 || self.last_dist == self.lit_bufsize as i32
     }
 
     fn d_code(&self, dist: usize) -> usize {
@@ -501,11 +514,61 @@ impl<'a> Trees<'a> {
         }
     }
 
+    /// Code lengths of the static literal/length and distance trees.
+    pub(crate) fn static_bit_lengths(&self) -> (Vec<u16>, Vec<u16>) {
+        (
+            self.static_ltree[..L_CODES].iter().map(|node| node.len).collect(),
+            self.static_dtree.iter().map(|node| node.len).collect(),
+        )
+    }
+
+    /// Build the dynamic trees for the symbols tallied so far and return
+    /// their code lengths and the cost of the tallied data in bits.
+    /// The tallies are discarded afterwards, so the block can be parsed again.
+    pub(crate) fn dynamic_bit_lengths(&mut self, state: &GzipState) -> (Vec<u16>, Vec<u16>, u64) {
+        self.build_tree(state, TreeType::Literal);
+        self.build_tree(state, TreeType::Distance);
+
+        let llens = self.dyn_ltree[..L_CODES].iter().map(|node| node.len).collect();
+        let dlens = self.dyn_dtree[..D_CODES].iter().map(|node| node.len).collect();
+        let cost = self.opt_len;
+
+        self.init_block();
+        (llens, dlens, cost)
+    }
+
+    /// Bits needed to send the literal `c` with the code lengths `llens`.
+    /// Symbols without a code are charged MAX_BITS.
+    pub(crate) fn literal_cost(&self, llens: &[u16], c: u8) -> u32 {
+        Self::code_cost(llens[c as usize])
+    }
+
+    /// Bits needed to send a match length (MIN_MATCH..MAX_MATCH), extra bits included.
+    pub(crate) fn length_cost(&self, llens: &[u16], len: usize) -> u32 {
+        let code = self.length_code[len - MIN_MATCH] as usize;
+        Self::code_cost(llens[code + LITERALS + 1]) + EXTRA_LBITS[code] as u32
+    }
+
+    /// Bits needed to send a match distance (1..=MAX_DIST), extra bits included.
+    pub(crate) fn dist_cost(&self, dlens: &[u16], dist: usize) -> u32 {
+        let code = self.d_code(dist - 1);
+        Self::code_cost(dlens[code]) + EXTRA_DBITS[code] as u32
+    }
+
+    fn code_cost(len: u16) -> u32 {
+        if len == 0 {
+            MAX_BITS as u32
+        } else {
+            len as u32
+        }
+    }
+
     pub(crate) fn flush_block(
         &mut self,
         state: &mut GzipState,
         buf: Option<&[u8]>,
         stored_len: u64,
+        pad: bool,
         eof: bool,
     ) -> i64 {
         let mut opt_lenb: u64;
@@ -514,7 +577,7 @@ impl<'a> Trees<'a> {
         // println!("flush_block");
 
         // Save the flags for the last 8 items
-        self.flag_buf[self.last_flags as usize] = self.flags as usize;
+        self.flag_buf[self.last_flags as usize] = self.flags;
 
         // Check if the file is ASCII or binary
         if self.file_type == None {
@@ -523,8 +586,10 @@ impl<'a> Trees<'a> {
         // println!("flush_block: stored_len: {}", stored_len);
         // Special handling for empty files
         if stored_len == 0 && eof {
-            // Use stored block format for empty files
+            // Use stored block format for empty files: an empty last block
+            // still needs its lengths
             state.send_bits((STORED_BLOCK << 1) as u16 + (if eof { 1 } else { 0 }) as u16, 3);
+            self.copy_block(state, &[], 0, true);
             self.compressed_len = 0;
             self.file_method = STORED;
             return 0; // Return compressed length (0 for empty file)
@@ -555,8 +620,8 @@ impl<'a> Trees<'a> {
         // println!("max_blindex: {}", max_blindex);
 
         // Determine the best encoding. Compute the block length in bytes
-        opt_lenb = (self.opt_len + 3 + 7) >> 3;
-        static_lenb = (self.static_len.wrapping_add(3 + 7)) >> 3;
+        opt_lenb = self.opt_len.saturating_add(3 + 7) >> 3;
+        static_lenb = self.static_len.saturating_add(3 + 7) >> 3;
         self.input_len += stored_len; // For debugging only
 
         if state.verbose > 0 {
@@ -572,7 +637,7 @@ impl<'a> Trees<'a> {
             );
         }
 
-        if static_lenb <= opt_lenb {
+        if static_lenb <= opt_lenb || self.strategy == Strategy::Fixed {
             opt_lenb = static_lenb;
         }
 
@@ -581,7 +646,8 @@ impl<'a> Trees<'a> {
         }
 
         if stored_len <= opt_lenb && eof && self.compressed_len == 0 && seekable() {
-            // Since LIT_BUFSIZE <= 2*WSIZE, the input data must be there
+            // The block may have left the window when the literal buffer
+            // is larger than the window
             if buf.is_none() {
                 state.gzip_error("block vanished");
             }
@@ -621,7 +687,13 @@ impl<'a> Trees<'a> {
         if eof {
             //assert!(self.input_len as i64 == state.bytes_in, "bad input size");
             state.bi_windup();
-            self.compressed_len = self.compressed_len.wrapping_add(7); // Align on byte boundary
+            self.compressed_len += 7; // Align on byte boundary
+        } else if pad && !self.compressed_len.is_multiple_of(8) {
+            // Align on a byte boundary with an empty stored block
+            state.send_bits((STORED_BLOCK << 1) as u16, 3);
+            self.compressed_len = (self.compressed_len + 3 + 7) & !7u64;
+            self.compressed_len += 4 << 3;
+            self.copy_block(state, &[], 0, true);
         }
 
         (self.compressed_len >> 3) as i64
@@ -799,11 +871,11 @@ impl<'a> Trees<'a> {
             while lx < self.last_lit as usize {
                 // 每8个字面值加载一个新的标志字节
                 if (lx & 7) == 0 {
-                    flag = self.flag_buf[fx] as u8;
+                    flag = self.flag_buf[fx];
                     fx += 1;
                 }
 
-                lc = state.inbuf[lx] as i32;
+                lc = self.l_buf[lx] as i32;
                 lx += 1;
 
                 if (flag & 1) == 0 {
@@ -936,6 +1008,10 @@ impl<'a> Trees<'a> {
         }
 
         // 确保至少有两个非零频率的码
+        // The forced codes are not sent: what gen_bitlen counts for them is
+        // taken off again afterwards, so the lengths never go below zero
+        let mut forced_opt = 0u64;
+        let mut forced_static = 0u64;
         while self.heap_len < 2 {
             let new_node = if max_code < 2 {
                 max_code += 1;
@@ -954,7 +1030,7 @@ impl<'a> Trees<'a> {
                 TreeType::BitLength => self.bl_tree[new_node].freq = 1,
             }
             self.depth[new_node] = 0;
-            self.opt_len = self.opt_len.wrapping_sub(1);
+            forced_opt += 1;
             
             // 如果是字面树或距离树，更新静态长度
             if tree_type != TreeType::BitLength {
@@ -963,7 +1039,7 @@ impl<'a> Trees<'a> {
                     TreeType::Distance => self.static_dtree[new_node].len,
                     TreeType::BitLength => 0,
                 };
-                self.static_len = self.static_len.wrapping_sub(static_len as u64);
+                forced_static += static_len as u64;
             }
         }
 
@@ -1032,6 +1108,8 @@ impl<'a> Trees<'a> {
 
         // 生成位长度
         self.gen_bitlen(state, tree_type);
+        self.opt_len = self.opt_len.saturating_sub(forced_opt);
+        self.static_len = self.static_len.saturating_sub(forced_static);
 
         // 生成所有树节点的编码
         self.gen_codes(tree_type, TreeKind::Dynamic);
@@ -1138,7 +1216,7 @@ impl<'a> Trees<'a> {
             } as u64;
 
             // 更新优化长度
-            self.opt_len += freq * (bits as u64 + xbits as u64);
+            self.opt_len = self.opt_len.saturating_add(freq * (bits as u64 + xbits as u64));
 
             // 更新静态长度（如果不是位长度树）
             if tree_type != TreeType::BitLength {
@@ -1147,7 +1225,7 @@ impl<'a> Trees<'a> {
                     TreeType::Distance => self.static_dtree[n].len,
                     TreeType::BitLength => 0,
                 } as u64;
-                self.static_len += freq * (static_len + xbits as u64);
+                self.static_len = self.static_len.saturating_add(freq * (static_len + xbits as u64));
             }
         }
 
@@ -1204,7 +1282,11 @@ impl<'a> Trees<'a> {
                         TreeType::Distance => self.dyn_dtree[m].freq,
                         TreeType::BitLength => self.bl_tree[m].freq,
                     } as u64;
-                    self.opt_len += ((bits as i64 - current_len as i64) * freq as i64) as u64;
+                    if bits as u16 > current_len {
+                        self.opt_len = self.opt_len.saturating_add((bits as u64 - current_len as u64) * freq);
+                    } else {
+                        self.opt_len = self.opt_len.saturating_sub((current_len as u64 - bits as u64) * freq);
+                    }
                     
                     // 更新长度
                     match tree_type {
@@ -1341,7 +1423,7 @@ impl<'a> Trees<'a> {
         }
 
         // Update opt_len to include the bit length tree and counts
-        self.opt_len = self.opt_len.wrapping_add(3 * ((max_blindex as u64) + 1) + 5 + 5 + 4);
+        self.opt_len = self.opt_len.saturating_add(3 * ((max_blindex as u64) + 1) + 5 + 5 + 4);
 
         if state.verbose > 1 {
             eprintln!("\ndyn trees: dyn {}, stat {}", self.opt_len, self.static_len);
@@ -1397,7 +1479,7 @@ impl<'a> Trees<'a> {
             } else {
                 if count < min_count {
                     // Update the frequency for the current code length
-                    self.bl_tree[curlen as usize].freq += count as u16;
+                    self.bl_tree[curlen as usize].freq += count as u32;
                 } else if curlen != 0 {
                     if curlen != prevlen {
                         self.bl_tree[curlen as usize].freq += 1;
@@ -1,10 +1,8 @@
 use std::io;
 use std::time::SystemTime;
-use std::io::{stdout, Read, Write};
-use crate::{OK, ERROR, GzipState, STORED, DEFLATED, GZIP_MAGIC, ORIG_NAME, OS_CODE, INBUFSIZ, INBUF_EXTRA, OUTBUFSIZ, OUTBUF_EXTRA, DIST_BUFSIZE, WSIZE};
-use crate::deflate::Deflate;
+use std::io::{stdout, Write};
+use crate::{Container, OK, ERROR, GzipState, STORED, DEFLATED, GZIP_MAGIC, ORIG_NAME, OS_CODE, INBUFSIZ, INBUF_EXTRA, OUTBUFSIZ, OUTBUF_EXTRA, DIST_BUFSIZE, WSIZE};
 use crate::inflate::Inflate;
-use crate::trees::Trees;
 use std::backtrace::Backtrace;
 
 fn example_function() {
@@ -60,6 +58,16 @@ pub fn unzip (state: &mut GzipState) -> io::Result<()> {
     let mut inflate = Inflate::new();
 
     state.updcrc(None, 0); // initialize crc
+    state.adler = 1;
+
+    // Prime the window with the preset dictionary: distances reaching back
+    // before the start of the output wrap around to its end.
+    if state.preset_dict {
+        if let Some(dict) = &state.dictionary {
+            let dict = &dict[dict.len().saturating_sub(WSIZE)..];
+            state.window[WSIZE - dict.len()..WSIZE].copy_from_slice(dict);
+        }
+    }
 
     if pkzip>0 && ext_header == 0 {  // crc and length at the end otherwise
         orig_crc = LG(&state.inbuf[LOCCRC..]);
@@ -75,10 +83,18 @@ pub fn unzip (state: &mut GzipState) -> io::Result<()> {
 
         if res == 3 {
             state.gzip_error("memory exhausted");
+        } else if let Some(err) = inflate.limit_error.take() {
+            state.file_error(&err.to_string());
+            return Err(err);
         } else if res != 0 {
-            // eprintln!("Debugging res: {}", res);
-            eprintln!("");
-            state.gzip_error("invalid compressed data--format violated");
+            // Fail this file only: the next one may still be fine
+            let msg = if res == 4 {
+                "unexpected end of file"
+            } else {
+                "invalid compressed data--format violated"
+            };
+            state.file_error(msg);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
         }
     } else if pkzip>0 && state.method == STORED {
         let mut n = LG(&state.inbuf[LOCLEN..]);
@@ -89,7 +105,11 @@ pub fn unzip (state: &mut GzipState) -> io::Result<()> {
         }
         while n > 0 {
             let c: u8 = inflate.get_byte(state)?;
-            state.put_byte(c);
+            state.window[state.outcnt] = c;
+            state.outcnt += 1;
+            if state.outcnt == WSIZE {
+                inflate.flush_window(state)?;
+            }
             n -= 1;
         }
         inflate.flush_window(state);
@@ -97,6 +117,15 @@ pub fn unzip (state: &mut GzipState) -> io::Result<()> {
         state.gzip_error("internal error, invalid method");
     }
 
+    // --recover lost the end of the member along with its trailer
+    if inflate.skip_trailer {
+        return Ok(());
+    }
+
+    if state.container != Container::Gzip {
+        return check_zlib_trailer(state, &mut inflate);
+    }
+
     // Get the crc and original length
     if pkzip == 0 {
         // crc32 (see algorithm.doc)
@@ -127,21 +156,19 @@ pub fn unzip (state: &mut GzipState) -> io::Result<()> {
     // example_function();
 
     // Validate decompression
-    let mut dp_crc = state.updcrc(Some(&state.outbuf.clone()), 0);
+    let mut dp_crc = state.updcrc(Some(&[]), 0);
     // println!("orig_crc={:?} dp_crc={:?}\n",orig_crc, dp_crc);
+    // Data skipped by --recover cannot match the trailer
+    if inflate.damaged {
+        return Ok(());
+    }
     if  u32::from(orig_crc) != dp_crc {
         // let backtrace = Backtrace::capture(); println!("{:?}", backtrace);
-        eprintln!(
-            "\n{}: {}: invalid compressed data--crc error",
-            state.program_name, state.ifname
-        );
+        state.file_error("invalid compressed data--crc error");
         err = ERROR;
     }
     if  u32::from(orig_len) != (state.bytes_out & 0xffffffff) as u32 {
-        eprintln!(
-            "\n{}: {}: invalid compressed data--length error",
-            state.program_name, state.ifname
-        );
+        state.file_error("invalid compressed data--length error");
         err = ERROR;
     }
 
@@ -150,14 +177,15 @@ pub fn unzip (state: &mut GzipState) -> io::Result<()> {
         if state.to_stdout {
             eprintln!(
                 "{}: {} has more than one entry--rest ignored",
-                state.program_name, state.ifname
+                state.program_name, state.ifname.display()
             );
         } else {
             // Don't destroy the input zip file
             eprintln!(
                 "{}: {} has more than one entry -- unchanged",
-                state.program_name, state.ifname
+                state.program_name, state.ifname.display()
             );
+            state.record_error("has more than one entry -- unchanged");
             err = ERROR;
         }
     }
@@ -175,3 +203,20 @@ pub fn unzip (state: &mut GzipState) -> io::Result<()> {
 
     return Err(io::Error::new(io::ErrorKind::Other, "Decompression error"));
 }
+
+/// Check the Adler-32 trailer (big endian) of a zlib stream. Raw deflate
+/// streams carry no trailer.
+fn check_zlib_trailer(state: &mut GzipState, inflate: &mut Inflate) -> io::Result<()> {
+    if state.container == Container::Raw {
+        return Ok(());
+    }
+    let mut buf = [0u8; 4];
+    for byte in buf.iter_mut() {
+        *byte = inflate.get_byte(state)?;
+    }
+    if u32::from_be_bytes(buf) != state.adler && !inflate.damaged {
+        state.file_error("invalid compressed data--adler32 error");
+        return Err(io::Error::new(io::ErrorKind::Other, "Decompression error"));
+    }
+    Ok(())
+}
@@ -1,62 +1,55 @@
 use std::io;
+use std::os::unix::ffi::OsStrExt;
 use std::time::SystemTime;
-use crate::{GzipState, DEFLATED, GZIP_MAGIC, ORIG_NAME, OS_CODE};
+use crate::{adler32, Container, GzipState, DEFLATED, FDICT, GZIP_MAGIC, ORIG_NAME, OS_CODE, OS_UNKNOWN};
+use crate::deflate::Strategy;
 use crate::deflate::Deflate;
 use crate::trees::Trees;
 
 pub fn zip (state: &mut GzipState) -> io::Result<()> {
-    // Initialize output count
+    // Initialize output count and checksums
     state.outcnt = 0;
-
-    // Write the gzip header
+    state.crc = state.updcrc(None, 0);
+    state.adler = 1;
     state.method = DEFLATED;
-    state.put_byte(GZIP_MAGIC[0])?;
-    state.put_byte(GZIP_MAGIC[1])?;
-    state.put_byte(DEFLATED as u8)?;
-    let mut flags = 0;
 
-    if state.save_orig_name {
-        flags |= ORIG_NAME;
+    if state.container == Container::Gzip {
+        write_gzip_header(state)?;
     }
-    state.put_byte(flags)?;         // general flags
-
-    let stamp = if let Some(time_stamp) = state.time_stamp {
-        match time_stamp.duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(duration) => {
-                let secs = duration.as_secs();
-                if secs <= u32::MAX as u64 {
-                    secs as u32
-                } else {
-                    0
-                }
-            }
-            Err(_) => 0,
-        }
-    } else {
-        0
-    };
-
-    state.put_long(stamp)?;
 
     // Initialize compression (bi_init, ct_init, lm_init)
-    let mut trees = Trees::new();
-    let mut deflate = Deflate::new();
+    let mut trees = Trees::with_mem_level(state.mem_level);
+    let mut deflate = Deflate::with_params(state.window_bits, state.mem_level);
     let mut attr = 0;
     let mut deflate_flags = 0;
     trees.ct_init(&mut attr, state.method);
+    trees.strategy = state.strategy;
+    deflate.set_strategy(state.strategy);
+    deflate.set_rsyncable(state.rsyncable);
+    if let Some(dict) = &state.dictionary {
+        deflate.set_dictionary(dict);
+    }
     deflate.lm_init(state, state.level, &mut deflate_flags);
 
-    // Write deflate flags and OS identifier
-    state.put_byte(deflate_flags as u8)?; // Assuming `deflate_flags` fits in u8
-    state.put_byte(OS_CODE)?;
+    match state.container {
+        Container::Gzip => {
+            // Write deflate flags and OS identifier. The flags only depend
+            // on the options, so --reproducible just has to hide the OS.
+            state.put_byte(deflate_flags as u8)?; // Assuming `deflate_flags` fits in u8
+            state.put_byte(if state.reproducible { OS_UNKNOWN } else { OS_CODE })?;
 
-    // Write original filename if `save_orig_name` is set
-    if state.save_orig_name {
-        let basename = state.gzip_base_name(&state.ifname).to_string();
-        for byte in basename.bytes() {
-            state.put_byte(byte)?;
+            // Write original filename if `save_orig_name` is set
+            if state.save_orig_name {
+                // The name is stored as raw bytes, whatever its encoding
+                let basename = state.gzip_base_name(&state.ifname).as_bytes().to_vec();
+                for byte in basename {
+                    state.put_byte(byte)?;
+                }
+                state.put_byte(0)?; // Null-terminate the filename
+            }
         }
-        state.put_byte(0)?; // Null-terminate the filename
+        Container::Zlib => write_zlib_header(state, deflate.window_bits())?,
+        Container::Raw => {}
     }
 
     // Record header bytes
@@ -71,19 +64,100 @@ pub fn zip (state: &mut GzipState) -> io::Result<()> {
         if state.ifile_size != -1 && state.bytes_in != state.ifile_size {
             eprintln!(
                 "{}: {}: file size changed while zipping",
-                state.program_name, state.ifname
+                state.program_name, state.ifname.display()
             );
         }
     }
 
-    // Write the CRC and uncompressed size
-    let crc_value = state.crc;
-    let uncompressed_size = state.bytes_in.try_into().unwrap();
+    match state.container {
+        Container::Gzip => {
+            // Write the CRC and uncompressed size
+            let crc_value = state.crc;
+            let uncompressed_size = state.bytes_in.try_into().unwrap();
+
+            state.put_long(crc_value)?;
+            state.put_long(uncompressed_size)?;
+
+            state.header_bytes += 8; // 2 * 4 bytes
+        }
+        Container::Zlib => {
+            // Adler-32 of the uncompressed data, most significant byte first
+            for byte in state.adler.to_be_bytes() {
+                state.put_byte(byte)?;
+            }
+            state.header_bytes += 4;
+        }
+        Container::Raw => {}
+    }
+
+    state.flush_outbuf()?;
+    Ok(())
+}
+
+// Write the gzip header up to the time stamp; the extra flags depend on
+// lm_init and follow it.
+fn write_gzip_header(state: &mut GzipState) -> io::Result<()> {
+    state.put_byte(GZIP_MAGIC[0])?;
+    state.put_byte(GZIP_MAGIC[1])?;
+    state.put_byte(DEFLATED as u8)?;
+    let mut flags = 0;
+
+    if state.save_orig_name {
+        flags |= ORIG_NAME;
+    }
+    state.put_byte(flags)?;         // general flags
+
+    let stamp = if let Some(time_stamp) = state.time_stamp {
+        match time_stamp.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(duration) => {
+                let secs = duration.as_secs();
+                if secs <= u32::MAX as u64 {
+                    secs as u32
+                } else {
+                    0
+                }
+            }
+            Err(_) => 0,
+        }
+    } else {
+        0
+    };
+    // --reproducible clamps the time stamp to SOURCE_DATE_EPOCH
+    let stamp = if state.reproducible {
+        (stamp as u64).min(state.source_date_epoch) as u32
+    } else {
+        stamp
+    };
 
-    state.put_long(crc_value)?;
-    state.put_long(uncompressed_size)?;
+    state.put_long(stamp)?;
+    Ok(())
+}
 
-    state.header_bytes += 8; // 2 * 4 bytes
+// Write the zlib header: CMF with the window size, FLG with the level hint
+// and the id of the preset dictionary, if any.
+fn write_zlib_header(state: &mut GzipState, window_bits: u32) -> io::Result<()> {
+    let cmf: u8 = ((window_bits - 8) << 4) as u8 | DEFLATED as u8;
+    let level_hint: u8 = if state.level < 2 || state.strategy == Strategy::HuffmanOnly {
+        0
+    } else if state.level < 6 {
+        1
+    } else if state.level == 6 {
+        2
+    } else {
+        3
+    };
+    let mut flg = level_hint << 6;
+    if state.dictionary.is_some() {
+        flg |= FDICT;
+    }
+    flg += 31 - ((u16::from(cmf) << 8 | u16::from(flg)) % 31) as u8;
+    state.put_byte(cmf)?;
+    state.put_byte(flg)?;
 
+    if let Some(dict) = &state.dictionary {
+        for byte in adler32(1, dict).to_be_bytes() {
+            state.put_byte(byte)?;
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}